@@ -0,0 +1,207 @@
+use std::time::{Duration, Instant};
+
+//What the quality controller tries to reach
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    //Frames per second, counting only the time spent encoding and sending
+    Fps(f32),
+    //Bits per second on the transport
+    Bitrate(u32),
+}
+
+//Settings of the quality controller, it only runs when there is a target
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveOptions {
+    pub target: Option<Target>,
+    pub min_quality: u8,
+    pub max_quality: u8,
+    //Smallest fraction of the resolution the frames can be shrunk to, 1 keeps the resolution
+    pub min_scale: f32,
+}
+
+impl Default for AdaptiveOptions {
+    fn default() -> Self {
+        AdaptiveOptions {
+            target: None,
+            min_quality: 10,
+            max_quality: 90,
+            min_scale: 1.0,
+        }
+    }
+}
+
+//How long the measures are gathered before the quality is moved
+const WINDOW: Duration = Duration::from_secs(1);
+const QUALITY_STEP: u8 = 5;
+pub const SCALE_STEP: f32 = 0.8;
+//Below this fraction of the target there is room to raise the quality again,
+//the gap keeps the controller from going up and down every window
+const HEADROOM: f32 = 0.7;
+
+//Moves the quality, and the resolution once the quality is at its lowest, to hit the target
+pub struct QualityController {
+    options: AdaptiveOptions,
+    target: Target,
+    quality: u8,
+    scale: f32,
+    window_start: Instant,
+    frames: u32,
+    bytes: usize,
+    busy: Duration,
+}
+
+impl QualityController {
+    //None if the options have no target
+    pub fn new(options: AdaptiveOptions, quality: u8) -> Option<Self> {
+        let min_quality = options.min_quality.clamp(1, 100);
+        let options = AdaptiveOptions {
+            min_quality,
+            max_quality: options.max_quality.clamp(min_quality, 100),
+            min_scale: options.min_scale.clamp(0.05, 1.0),
+            ..options
+        };
+        Some(QualityController {
+            target: options.target?,
+            quality: quality.clamp(options.min_quality, options.max_quality),
+            options,
+            scale: 1.0,
+            window_start: Instant::now(),
+            frames: 0,
+            bytes: 0,
+            busy: Duration::ZERO,
+        })
+    }
+
+    pub fn quality(&self) -> u8 {
+        self.quality
+    }
+
+    //Fraction of the resolution the frames are sent at
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    //Starts measuring again, the time without a receiver does not count
+    pub fn restart(&mut self) {
+        self.window_start = Instant::now();
+        self.frames = 0;
+        self.bytes = 0;
+        self.busy = Duration::ZERO;
+    }
+
+    //Records a sent frame, returns true if the quality or the scale changed
+    pub fn record(&mut self, bytes: usize, busy: Duration) -> bool {
+        self.frames += 1;
+        self.bytes += bytes;
+        self.busy += busy;
+        let elapsed = self.window_start.elapsed();
+        if elapsed < WINDOW {
+            return false;
+        }
+
+        //Above 1 the target is missed
+        let load = match self.target {
+            Target::Fps(fps) => self.busy.as_secs_f32() / self.frames as f32 * fps,
+            Target::Bitrate(bps) => self.bytes as f32 * 8.0 / elapsed.as_secs_f32() / bps.max(1) as f32,
+        };
+        self.restart();
+
+        let (quality, scale) = (self.quality, self.scale);
+        if load > 1.0 {
+            //The quality goes first, a smaller image is the last resort. Far from the target it moves faster
+            let step = if load > 2.0 { QUALITY_STEP * 2 } else { QUALITY_STEP };
+            if self.quality > self.options.min_quality {
+                self.quality = self.quality.saturating_sub(step).max(self.options.min_quality);
+            } else {
+                self.scale = (self.scale * SCALE_STEP).max(self.options.min_scale);
+            }
+        } else if load < HEADROOM {
+            //A step up in scale grows the frames by its square, it has to fit too
+            if self.scale < 1.0 {
+                if load < HEADROOM * SCALE_STEP * SCALE_STEP {
+                    self.scale = (self.scale / SCALE_STEP).min(1.0);
+                }
+            } else {
+                self.quality = (self.quality + QUALITY_STEP).min(self.options.max_quality);
+            }
+        }
+        quality != self.quality || scale != self.scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller(target: Target, quality: u8, min_scale: f32) -> QualityController {
+        let options = AdaptiveOptions {
+            target: Some(target),
+            min_quality: 20,
+            max_quality: 80,
+            min_scale,
+        };
+        QualityController::new(options, quality).unwrap()
+    }
+
+    //Ends the window with one more frame, as if it started a window ago
+    fn end_window(controller: &mut QualityController, bytes: usize, busy: Duration) -> bool {
+        controller.window_start = Instant::now() - WINDOW;
+        controller.record(bytes, busy)
+    }
+
+    const FRAME_AT_30: Duration = Duration::from_micros(33_333);
+
+    #[test]
+    fn nothing_moves_inside_the_window() {
+        let mut controller = controller(Target::Fps(30.0), 50, 1.0);
+        for _ in 0..10 {
+            assert!(!controller.record(1000, FRAME_AT_30 * 3));
+        }
+        assert_eq!(controller.quality(), 50);
+    }
+
+    #[test]
+    fn steps_down_faster_far_from_the_target() {
+        let mut controller = controller(Target::Fps(30.0), 50, 1.0);
+        assert!(end_window(&mut controller, 1000, FRAME_AT_30 * 3 / 2));
+        assert_eq!(controller.quality(), 45);
+        assert!(end_window(&mut controller, 1000, FRAME_AT_30 * 3));
+        assert_eq!(controller.quality(), 35);
+    }
+
+    #[test]
+    fn headroom_before_going_up() {
+        let mut controller = controller(Target::Fps(30.0), 50, 1.0);
+        //Close to the target nothing changes
+        assert!(!end_window(&mut controller, 1000, FRAME_AT_30 * 8 / 10));
+        assert_eq!(controller.quality(), 50);
+        assert!(end_window(&mut controller, 1000, FRAME_AT_30 / 2));
+        assert_eq!(controller.quality(), 55);
+        controller.quality = 80;
+        assert!(!end_window(&mut controller, 1000, FRAME_AT_30 / 2));
+        assert_eq!(controller.quality(), 80);
+    }
+
+    #[test]
+    fn scale_once_the_quality_is_lowest() {
+        let mut controller = controller(Target::Bitrate(80_000), 25, 0.5);
+        //About 10 kB in the window is 80 kbit/s, twice that is over the target
+        assert!(end_window(&mut controller, 20_000, Duration::ZERO));
+        assert_eq!((controller.quality(), controller.scale()), (20, 1.0));
+        assert!(end_window(&mut controller, 20_000, Duration::ZERO));
+        assert_eq!(controller.scale(), SCALE_STEP);
+        //0.64, 0.512 and then the minimum
+        for _ in 0..3 {
+            assert!(end_window(&mut controller, 20_000, Duration::ZERO));
+        }
+        assert_eq!(controller.scale(), 0.5);
+        assert!(!end_window(&mut controller, 20_000, Duration::ZERO));
+
+        //Under the headroom but a bigger scale would not fit
+        assert!(!end_window(&mut controller, 5_000, Duration::ZERO));
+        assert_eq!(controller.scale(), 0.5);
+        //The scale comes back before the quality
+        assert!(end_window(&mut controller, 2_000, Duration::ZERO));
+        assert_eq!((controller.quality(), controller.scale()), (20, 0.5 / SCALE_STEP));
+    }
+}
@@ -0,0 +1,238 @@
+use crate::adaptive::{AdaptiveOptions, SCALE_STEP};
+use crate::encoder::Encoder;
+use crate::frame::Frame;
+use crate::scale::{Scaler, Size};
+
+//Positions tried above the last one when a frame fits with room left
+const STEP: usize = 5;
+
+//Picks the highest quality and scale whose frames fit in max_bytes.
+//Every scale and quality is a position, a higher position is a bigger frame, the scale only
+//goes down once the lowest quality does not fit. The search starts at the position of the last frame
+pub struct FrameBudget {
+    max_bytes: usize,
+    min_quality: u8,
+    qualities: usize,
+    scales: usize,
+    position: usize,
+}
+
+impl FrameBudget {
+    pub fn new(max_bytes: usize, bounds: &AdaptiveOptions, quality: u8) -> Self {
+        let min_quality = bounds.min_quality.clamp(1, 100);
+        let max_quality = bounds.max_quality.clamp(min_quality, 100);
+        let min_scale = bounds.min_scale.clamp(0.05, 1.0);
+        let qualities = (max_quality - min_quality) as usize + 1;
+        let mut scales = 1;
+        while SCALE_STEP.powi(scales) >= min_scale {
+            scales += 1;
+        }
+        let quality = quality.clamp(min_quality, max_quality);
+        FrameBudget {
+            max_bytes,
+            min_quality,
+            qualities,
+            scales: scales as usize,
+            position: (scales as usize - 1) * qualities + (quality - min_quality) as usize,
+        }
+    }
+
+    //Quality and scale of a position
+    fn level(&self, position: usize) -> (u8, f32) {
+        let quality = self.min_quality + (position % self.qualities) as u8;
+        let scale = SCALE_STEP.powi((self.scales - 1 - position / self.qualities) as i32);
+        (quality, scale)
+    }
+
+    fn encode_at(
+        &self,
+        position: usize,
+        frame: &Frame,
+        encoder: &mut dyn Encoder,
+        scaler: &mut Scaler,
+    ) -> Result<(Vec<u8>, Size), String> {
+        let (quality, scale) = self.level(position);
+        encoder.set_quality(quality)?;
+        let frame = scaler.resize(*frame, scale);
+        let size = Size {
+            width: frame.width,
+            height: frame.height,
+        };
+        Ok((encoder.encode(&frame)?, size))
+    }
+
+    //The biggest encoding of the frame that fits and its size, None if not even the smallest one does
+    pub fn encode(
+        &mut self,
+        frame: &Frame,
+        encoder: &mut dyn Encoder,
+        scaler: &mut Scaler,
+    ) -> Result<Option<(Vec<u8>, Size)>, String> {
+        let last = self.scales * self.qualities - 1;
+        let guess = self.position.min(last);
+        let (encoded, size) = self.encode_at(guess, frame, encoder, scaler)?;
+        if encoded.len() <= self.max_bytes {
+            //Most frames are like the last one, so only one step up is tried and only with room left
+            if guess < last && encoded.len() < self.max_bytes / 10 * 9 {
+                let up = (guess + STEP).min(last);
+                let (bigger, bigger_size) = self.encode_at(up, frame, encoder, scaler)?;
+                if bigger.len() <= self.max_bytes {
+                    encoder.recycle(encoded);
+                    self.position = up;
+                    return Ok(Some((bigger, bigger_size)));
+                }
+                encoder.recycle(bigger);
+            }
+            self.position = guess;
+            return Ok(Some((encoded, size)));
+        }
+        encoder.recycle(encoded);
+
+        //Too big. Inside a scale the frames grow with the quality but the top of a scale is bigger than the
+        //bottom of the next one, so the scale is the first one down whose lowest quality fits
+        let mut start = guess - guess % self.qualities;
+        let mut high = guess;
+        let mut best = loop {
+            //When the guess is the lowest quality of its scale it is already known to be too big
+            if start < high {
+                let (encoded, size) = self.encode_at(start, frame, encoder, scaler)?;
+                if encoded.len() <= self.max_bytes {
+                    break Some((start, encoded, size));
+                }
+                encoder.recycle(encoded);
+            }
+            if start == 0 {
+                break None;
+            }
+            high = start;
+            start -= self.qualities;
+        };
+        //Then the quality is bisected inside that scale
+        let mut low = start + 1;
+        while best.is_some() && low < high {
+            let middle = (low + high) / 2;
+            let (encoded, size) = self.encode_at(middle, frame, encoder, scaler)?;
+            if encoded.len() <= self.max_bytes {
+                if let Some((_, a, _)) = best.replace((middle, encoded, size)) {
+                    encoder.recycle(a);
+                }
+                low = middle + 1;
+            } else {
+                encoder.recycle(encoded);
+                high = middle;
+            }
+        }
+        match best {
+            Some((position, encoded, size)) => {
+                self.position = position;
+                Ok(Some((encoded, size)))
+            }
+            None => {
+                self.position = 0;
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Codec;
+    use crate::frame::PixelFormat;
+    use crate::scale::ScaleOptions;
+
+    //Frames of quality * width bytes, it remembers every quality and width it encoded
+    #[derive(Default)]
+    struct Growing {
+        quality: u8,
+        tried: Vec<(u8, u32)>,
+    }
+
+    impl Encoder for Growing {
+        fn codec(&self) -> Codec {
+            Codec::Jpeg
+        }
+
+        fn encode(&mut self, frame: &Frame) -> Result<Vec<u8>, String> {
+            self.tried.push((self.quality, frame.width));
+            Ok(vec![0; self.quality as usize * frame.width as usize])
+        }
+
+        fn recycle(&mut self, _buffer: Vec<u8>) {}
+
+        fn set_quality(&mut self, quality: u8) -> Result<(), String> {
+            self.quality = quality;
+            Ok(())
+        }
+    }
+
+    //Quality 10 to 90 at the scales 1, 0.8, 0.64 and 0.512, the widths are 100, 80, 64 and 51
+    fn budget(max_bytes: usize) -> FrameBudget {
+        let bounds = AdaptiveOptions {
+            min_quality: 10,
+            max_quality: 90,
+            min_scale: 0.5,
+            ..Default::default()
+        };
+        FrameBudget::new(max_bytes, &bounds, 90)
+    }
+
+    //Quality and width of the frame that was chosen
+    fn encode(budget: &mut FrameBudget, encoder: &mut Growing) -> Option<(u8, u32)> {
+        let data = vec![0u8; 100 * 10 * 3];
+        let frame = Frame {
+            data: &data,
+            width: 100,
+            height: 10,
+            pitch: 300,
+            format: PixelFormat::Rgb,
+        };
+        let mut scaler = Scaler::new(ScaleOptions::default());
+        let result = budget.encode(&frame, encoder, &mut scaler).unwrap();
+        result.map(|(encoded, size)| {
+            assert!(encoded.len() <= budget.max_bytes);
+            ((encoded.len() / size.width as usize) as u8, size.width)
+        })
+    }
+
+    #[test]
+    fn largest_that_fits() {
+        //Quality 30 at the full width, not quality 58 at the smallest scale
+        let mut encoder = Growing::default();
+        assert_eq!(encode(&mut budget(3000), &mut encoder), Some((30, 100)));
+        let mut encoder = Growing::default();
+        assert_eq!(encode(&mut budget(900), &mut encoder), Some((11, 80)));
+    }
+
+    #[test]
+    fn bounds_are_kept() {
+        let mut encoder = Growing::default();
+        assert_eq!(encode(&mut budget(600), &mut encoder), Some((11, 51)));
+        assert!(encoder.tried.iter().all(|a| a.0 >= 10 && a.1 >= 51));
+    }
+
+    #[test]
+    fn none_when_nothing_fits() {
+        let mut budget = budget(500);
+        let mut encoder = Growing::default();
+        assert_eq!(encode(&mut budget, &mut encoder), None);
+        assert_eq!(budget.position, 0);
+    }
+
+    #[test]
+    fn starts_from_the_last_position() {
+        let mut budget = budget(3000);
+        encode(&mut budget, &mut Growing::default());
+        let mut encoder = Growing::default();
+        assert_eq!(encode(&mut budget, &mut encoder), Some((30, 100)));
+        //Same frame, the first try is the last choice and it fits without room for a step up
+        assert_eq!(encoder.tried, vec![(30, 100)]);
+
+        //A bigger budget moves one step up from there
+        budget.max_bytes = 10_000;
+        let mut encoder = Growing::default();
+        assert_eq!(encode(&mut budget, &mut encoder), Some((35, 100)));
+        assert_eq!(encoder.tried, vec![(30, 100), (35, 100)]);
+    }
+}
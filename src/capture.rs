@@ -1,216 +1,1277 @@
+use std::collections::HashMap;
+
 #[cfg(target_os = "linux")]
-pub fn capture_screen(quality: u8) -> xcb::Result<Vec<u8>> {
-    use std::u32;
+use crate::convert::{ByteOrder, PixelConverter, PixelLayout};
+use crate::encoder::EncoderOptions;
+use crate::error::{GabinatorError, Logger, LoggerLevel};
+use crate::frame::{Frame, PixelFormat};
+use crate::pipeline::Pipeline;
+use crate::source::{FrameSource, SourceKind};
+use crate::window::WindowSelector;
 
-    use image::RgbaImage;
-    use xcb::{
-        x::{self, GetImage, ImageOrder},
-        Connection,
-    };
-    let (conn, window_number) = Connection::connect(None)?;
-
-    let setup = conn.get_setup();
-    let mut window = setup.roots();
-    let current_window = window.next().unwrap();
-    let width = current_window.width_in_pixels() as u32;
-    let height = current_window.height_in_pixels() as u32;
-
-    let cookie_get_image = conn.send_request(&GetImage {
-        format: x::ImageFormat::ZPixmap,
-        drawable: x::Drawable::Window(current_window.root()),
-        x: 0,
-        y: 0,
-        width: width as u16,
-        height: height as u16,
-        plane_mask: u32::MAX,
-    });
-    let image_reply = conn.wait_for_reply(cookie_get_image).unwrap();
-    let data = image_reply.data();
-    let depth = image_reply.depth();
-    let pixmap = setup
-        .pixmap_formats()
-        .iter()
-        .find(|i| i.depth() == depth)
-        .unwrap();
-
-    let bits_per_pixel = pixmap.bits_per_pixel();
-    let bit_order = setup.bitmap_format_bit_order();
-    let mut image_data = vec![0u8; (width * height * 4) as usize];
-    for y in 0..height {
-        for x in 0..width {
-            let index = ((y * width + x) * bits_per_pixel as u32 / 8) as usize;
-            let (r, g, b, a) = match depth {
-                8 => {
-                    let pixel = if bit_order == ImageOrder::LsbFirst {
-                        data[index]
-                    } else {
-                        data[index] & 7 << 4 | data[index] >> 4
-                    };
+//Which part of the screen gets captured
+#[derive(Clone, Debug)]
+pub enum CaptureTarget {
+    //The whole root window
+    Screen,
+    //A RandR monitor, by output name or by index in the --list-monitors output
+    Monitor(String),
+    //A fixed rectangle, it follows the "region" key of the config while running
+    Region(Rect),
+    //A single window, it follows the window when it moves or gets resized
+    Window(WindowSelector),
+}
 
-                    (
-                        ((pixel >> 6) as f32 / 3.0 * 255.0) as u8,
-                        (((pixel >> 2) & 7) as f32 / 7.0 * 255.0) as u8,
-                        ((pixel & 3) as f32 / 3.0 * 255.0) as u8,
-                        255 as u8,
-                    )
-                }
+//Everything that changes what the capturer grabs
+#[derive(Clone, Debug)]
+pub struct CaptureOptions {
+    //Where the frames come from, the rest of the options only apply to the screen
+    pub source: SourceKind,
+    pub target: CaptureTarget,
+    //Read windows from their own XComposite pixmap, so they can be captured while covered
+    pub composite: bool,
+    //Use XDamage to skip the frames where nothing changed
+    pub damage: bool,
+    //Draw the mouse pointer over the frame
+    pub cursor: bool,
+    //The receiver can move the pointer and click, only with the screen source
+    pub input: bool,
+    //Copying on one side pastes on the other, for CLIPBOARD and PRIMARY
+    pub clipboard: bool,
+    //PNG images are synchronized too, not only text
+    pub clipboard_images: bool,
+}
 
-                16 => {
-                    let pixel = if bit_order == ImageOrder::LsbFirst {
-                        data[index] as u16 | (data[index + 1] as u16) << 8
-                    } else {
-                        (data[index] as u16) << 8 | data[index + 1] as u16
-                    };
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        CaptureOptions {
+            source: SourceKind::Screen,
+            target: CaptureTarget::Screen,
+            composite: true,
+            damage: true,
+            cursor: false,
+            input: false,
+            clipboard: false,
+            clipboard_images: false,
+        }
+    }
+}
 
-                    (
-                        ((pixel >> 11) as f32 / 31.0 * 255.0) as u8,
-                        (((pixel >> 5) & 63) as f32 / 63.0 * 255.0) as u8,
-                        ((pixel & 31) as f32 / 31.0 * 255.0) as u8,
-                        255 as u8,
-                    )
-                }
+//Rectangle in root window coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+}
 
-                24 | 32 => {
-                    if bit_order == ImageOrder::LsbFirst {
-                        (data[index + 2], data[index + 1], data[index], 255 as u8)
-                    } else {
-                        (data[index], data[index + 1], data[index + 2], 255 as u8)
-                    }
-                }
+impl Rect {
+    //Part of this rectangle that is inside the other one, None if they dont overlap
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x1 = (self.x as i32).max(other.x as i32);
+        let y1 = (self.y as i32).max(other.y as i32);
+        let x2 = (self.x as i32 + self.width as i32).min(other.x as i32 + other.width as i32);
+        let y2 = (self.y as i32 + self.height as i32).min(other.y as i32 + other.height as i32);
+        if x2 <= x1 || y2 <= y1 {
+            return None;
+        }
+        Some(Rect {
+            x: x1 as i16,
+            y: y1 as i16,
+            width: (x2 - x1) as u16,
+            height: (y2 - y1) as u16,
+        })
+    }
+}
 
-                _ => (0 as u8, 0 as u8, 0 as u8, 0 as u8),
-            };
+//Parses "x,y,width,height"
+impl std::str::FromStr for Rect {
+    type Err = String;
 
-            let local = ((y * width + x) * 4) as usize;
-            image_data[local] = r;
-            image_data[local + 1] = g;
-            image_data[local + 2] = b;
-            image_data[local + 3] = a;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<&str> = s.split(',').map(|a| a.trim()).collect();
+        if values.len() != 4 {
+            return Err(format!("Expected x,y,width,height but got {s}"));
+        }
+        let parse_error = |a: std::num::ParseIntError| format!("Not a valid region {s}: {a}");
+        let rect = Rect {
+            x: values[0].parse().map_err(parse_error)?,
+            y: values[1].parse().map_err(parse_error)?,
+            width: values[2].parse().map_err(parse_error)?,
+            height: values[3].parse().map_err(parse_error)?,
+        };
+        if rect.width == 0 || rect.height == 0 {
+            return Err(format!("The region {s} is empty"));
         }
+        Ok(rect)
     }
-    let image = RgbaImage::from_raw(width.into(), height.into(), image_data.clone()).unwrap();
-    let jpeg = turbojpeg::compress_image(&image, quality.into(), turbojpeg::Subsamp::Sub2x2).unwrap();
-    dbg!(jpeg.len());
-    return Ok(jpeg.as_ref().to_vec());
 }
 
-#[cfg(target_os = "windows")]
-pub fn capture_screen(quality: u8) -> Result<Vec<u8>, error::GabinatorError> {
-    use image::{
-        codecs::{
-            jpeg::{self, JpegEncoder},
-            png::{PngDecoder, PngEncoder},
-        },
-        ColorType, DynamicImage, RgbImage, Rgba,
+//A RandR output that is currently driven by a CRTC
+#[derive(Clone, Debug)]
+pub struct Monitor {
+    pub index: usize,
+    pub name: String,
+    pub output: u32,
+    pub crtc: u32,
+    pub area: Rect,
+}
+
+impl std::fmt::Display for Monitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} OUTPUT: {} CRTC: {} {}x{}+{}+{}",
+            self.index,
+            self.name,
+            self.output,
+            self.crtc,
+            self.area.width,
+            self.area.height,
+            self.area.x,
+            self.area.y
+        )
+    }
+}
+
+//Finds a monitor by its index or its output name
+pub fn find_monitor<'a>(monitors: &'a [Monitor], selector: &str) -> Option<&'a Monitor> {
+    match selector.parse::<usize>() {
+        Ok(index) => monitors.iter().find(|a| a.index == index),
+        Err(_) => monitors.iter().find(|a| a.name == selector),
+    }
+}
+
+//Captures and encodes a single frame, for long running sessions use a Pipeline instead
+pub fn capture_screen(encoder_options: &EncoderOptions, options: CaptureOptions) -> Result<Vec<u8>, GabinatorError> {
+    let mut pipeline = Pipeline::new(encoder_options, options)?;
+    //The first frame is never skipped
+    match pipeline.next_frame()? {
+        Some(a) => Ok(a.to_bytes()),
+        None => Err(GabinatorError::newCapture(
+            "No frame was captured",
+            LoggerLevel::Error,
+            None,
+        )),
+    }
+}
+
+//Lists the active RandR monitors of the default screen
+#[cfg(target_os = "linux")]
+pub fn list_monitors() -> Result<Vec<Monitor>, GabinatorError> {
+    let config = Logger::get_config_content();
+    let (conn, screen_number) = match xcb::Connection::connect(None) {
+        Ok(a) => a,
+        Err(a) => {
+            return Err(GabinatorError::newCapture(
+                format!("Not able to connect to the X server: {a}"),
+                LoggerLevel::Error,
+                Some(config),
+            ))
+        }
     };
-    use std::{
-        error::Error,
-        fs::File,
-        io::{BufWriter, Cursor, Write},
-        time::Instant,
-        u32,
+    let root = match conn.get_setup().roots().nth(screen_number as usize) {
+        Some(a) => a.root(),
+        None => {
+            return Err(GabinatorError::newCapture(
+                format!("The X server does not have the screen {screen_number}"),
+                LoggerLevel::Error,
+                Some(config),
+            ))
+        }
     };
+    match query_monitors(&conn, root) {
+        Ok(a) => Ok(a),
+        Err(a) => Err(GabinatorError::newCapture(
+            format!("Not able to query the RandR monitors: {a}"),
+            LoggerLevel::Error,
+            Some(config),
+        )),
+    }
+}
 
-    use image::{ExtendedColorType, ImageBuffer, ImageEncoder, ImageFormat, Rgb, RgbaImage};
-    use std::{
-        io::Read,
-        mem::size_of,
-        ptr::{null, null_mut},
-    };
-    use sysinfo::System;
-    use windows::{
-        core::HRESULT,
-        Win32::{
-            Graphics::Gdi::{
-                BitBlt, CreateCompatibleBitmap, CreateCompatibleDC, GetDC, GetDIBits, SelectObject,
-                SetStretchBltMode, StretchBlt, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, COLORONCOLOR,
-                DIB_RGB_COLORS, SRCCOPY,
+#[cfg(target_os = "linux")]
+fn query_monitors(conn: &xcb::Connection, root: xcb::x::Window) -> xcb::Result<Vec<Monitor>> {
+    use xcb::{randr, Xid};
+
+    let resources = conn.wait_for_reply(conn.send_request(&randr::GetScreenResourcesCurrent { window: root }))?;
+    let timestamp = resources.config_timestamp();
+
+    let mut monitors = Vec::new();
+    for output in resources.outputs() {
+        let output_info = conn.wait_for_reply(conn.send_request(&randr::GetOutputInfo {
+            output: *output,
+            config_timestamp: timestamp,
+        }))?;
+        let name = String::from_utf8_lossy(output_info.name()).to_string();
+        let crtc = output_info.crtc();
+        if output_info.connection() != randr::Connection::Connected || crtc.is_none() {
+            Logger::log(
+                format!("Output {name} is not active"),
+                LoggerLevel::Info,
+                None,
+            );
+            continue;
+        }
+
+        let crtc_info = conn.wait_for_reply(conn.send_request(&randr::GetCrtcInfo {
+            crtc,
+            config_timestamp: timestamp,
+        }))?;
+        monitors.push(Monitor {
+            index: monitors.len(),
+            name,
+            output: output.resource_id(),
+            crtc: crtc.resource_id(),
+            area: Rect {
+                x: crtc_info.x(),
+                y: crtc_info.y(),
+                width: crtc_info.width(),
+                height: crtc_info.height(),
             },
-            UI::WindowsAndMessaging::{
-                GetDesktopWindow, GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN, SM_XVIRTUALSCREEN,
-                SM_YVIRTUALSCREEN,
+        });
+    }
+    Ok(monitors)
+}
+
+//Long lived X11 capturer, keeps the connection and the screen geometry between frames
+#[cfg(target_os = "linux")]
+pub struct ScreenCapturer {
+    conn: xcb::Connection,
+    root: xcb::x::Window,
+    //Part of the drawable that gets captured, root coordinates unless a composite pixmap is used
+    area: Rect,
+    //Decodes the pixels of the root visual
+    converter: PixelConverter,
+    //Set when the encoder can read the pixels straight from the X server
+    native_format: Option<PixelFormat>,
+    //Last frame, in the native format or converted to RGBA, reused between frames
+    pixels: Vec<u8>,
+    backend: GrabBackend,
+    options: CaptureOptions,
+    //Size of the root window, captures are clipped to it
+    screen: Rect,
+    //Window being followed when the target is a window
+    window: Option<xcb::x::Window>,
+    //Offscreen pixmap of the followed window, only with XComposite
+    pixmap: Option<CompositePixmap>,
+    //Root coordinates of the followed window, the pixmap starts there
+    window_origin: (i16, i16),
+    //Last pointer image, drawn over the frames when the cursor option is on
+    cursor: Option<crate::cursor::CursorImage>,
+    //Change tracking, created on the first frame and again when the tracked drawable changes
+    damage: Option<crate::damage::DamageTracker>,
+    //The next frame is sent even if nothing changed
+    force_frame: bool,
+    //Area of the last frame that was sent
+    last_area: Option<Rect>,
+    //Changed parts of the last frame, relative to the captured area
+    dirty: Vec<Rect>,
+    //Last "region" value seen in the config and when it was read
+    region_config: Option<String>,
+    region_checked: std::time::Instant,
+    config: HashMap<String, String>,
+}
+
+//Pixmap that XComposite keeps updated with the content of a redirected window
+#[cfg(target_os = "linux")]
+struct CompositePixmap {
+    window: xcb::x::Window,
+    pixmap: xcb::x::Pixmap,
+    width: u16,
+    height: u16,
+}
+
+//How the pixels are transferred from the X server
+#[cfg(target_os = "linux")]
+enum GrabBackend {
+    //MIT-SHM, the server writes the image directly into a shared memory segment
+    Shm(ShmSegment),
+    //Plain GetImage, the whole image travels through the X socket
+    GetImage,
+}
+
+//System V shared memory segment attached to both this process and the X server
+#[cfg(target_os = "linux")]
+struct ShmSegment {
+    seg: xcb::shm::Seg,
+    address: *mut u8,
+    size: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl ShmSegment {
+    fn new(conn: &xcb::Connection, size: usize) -> Result<Self, String> {
+        //SAFETY: plain System V calls, the address is checked before being used
+        unsafe {
+            let shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+            if shmid < 0 {
+                return Err(format!("shmget failed: {}", std::io::Error::last_os_error()));
+            }
+            let address = libc::shmat(shmid, std::ptr::null(), 0);
+            if address as isize == -1 {
+                libc::shmctl(shmid, libc::IPC_RMID, std::ptr::null_mut());
+                return Err(format!("shmat failed: {}", std::io::Error::last_os_error()));
+            }
+
+            let seg: xcb::shm::Seg = conn.generate_id();
+            let attached = conn.send_and_check_request(&xcb::shm::Attach {
+                shmseg: seg,
+                shmid: shmid as u32,
+                read_only: false,
+            });
+            //Once both sides are attached the segment can be marked for removal,
+            //so it is freed even if the process dies without detaching
+            libc::shmctl(shmid, libc::IPC_RMID, std::ptr::null_mut());
+            if let Err(a) = attached {
+                libc::shmdt(address);
+                return Err(format!("The X server could not attach the segment: {a:?}"));
+            }
+
+            Ok(ShmSegment {
+                seg,
+                address: address as *mut u8,
+                size,
+            })
+        }
+    }
+
+    fn data(&self, len: usize) -> &[u8] {
+        //SAFETY: the segment lives as long as self and len is clamped to its size
+        unsafe { std::slice::from_raw_parts(self.address, len.min(self.size)) }
+    }
+
+    fn data_mut(&mut self, len: usize) -> &mut [u8] {
+        //SAFETY: same as data, the server only writes while a GetImage is pending
+        unsafe { std::slice::from_raw_parts_mut(self.address, len.min(self.size)) }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        //The server side is released when the connection closes
+        unsafe {
+            libc::shmdt(self.address as *const libc::c_void);
+        }
+    }
+}
+
+//MIT-SHM only works when the X server runs on this machine
+#[cfg(target_os = "linux")]
+fn is_local_display() -> bool {
+    let display = std::env::var("DISPLAY").unwrap_or_default();
+    match display.rsplit_once(':') {
+        Some((host, _)) => host.is_empty() || host == "unix" || host.starts_with('/'),
+        None => true,
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ScreenCapturer {
+    pub fn new(options: CaptureOptions) -> Result<Self, GabinatorError> {
+        let config = Logger::get_config_content();
+        let (conn, screen_number) = match xcb::Connection::connect_with_extensions(
+            None,
+            &[],
+            &[
+                xcb::Extension::Shm,
+                xcb::Extension::Composite,
+                xcb::Extension::Damage,
+                xcb::Extension::XFixes,
+            ],
+        ) {
+            Ok(a) => a,
+            Err(a) => {
+                return Err(GabinatorError::newCapture(
+                    format!("Not able to connect to the X server: {a}"),
+                    LoggerLevel::Error,
+                    Some(config),
+                ))
+            }
+        };
+
+        let setup = conn.get_setup();
+        let screen = match setup.roots().nth(screen_number as usize) {
+            Some(a) => a,
+            None => {
+                return Err(GabinatorError::newCapture(
+                    format!("The X server does not have the screen {screen_number}"),
+                    LoggerLevel::Error,
+                    Some(config),
+                ))
+            }
+        };
+        let root = screen.root();
+        let width = screen.width_in_pixels();
+        let height = screen.height_in_pixels();
+        let depth = screen.root_depth();
+        let format = match setup.pixmap_formats().iter().find(|i| i.depth() == depth) {
+            Some(a) => a,
+            None => {
+                return Err(GabinatorError::newCapture(
+                    format!("No pixmap format for depth {depth}"),
+                    LoggerLevel::Error,
+                    Some(config),
+                ))
+            }
+        };
+        let visual = screen
+            .allowed_depths()
+            .flat_map(|a| a.visuals())
+            .find(|a| a.visual_id() == screen.root_visual());
+        let layout = PixelLayout {
+            bits_per_pixel: format.bits_per_pixel(),
+            scanline_pad: format.scanline_pad(),
+            byte_order: match setup.image_byte_order() {
+                xcb::x::ImageOrder::LsbFirst => ByteOrder::LsbFirst,
+                xcb::x::ImageOrder::MsbFirst => ByteOrder::MsbFirst,
             },
-        },
-    };
+            red_mask: visual.map(|a| a.red_mask()).unwrap_or(0),
+            green_mask: visual.map(|a| a.green_mask()).unwrap_or(0),
+            blue_mask: visual.map(|a| a.blue_mask()).unwrap_or(0),
+        };
+        let converter = match PixelConverter::new(layout) {
+            Ok(a) => a,
+            Err(a) => {
+                return Err(GabinatorError::newCapture(
+                    format!("Not able to read the pixels of depth {depth}: {a}"),
+                    LoggerLevel::Error,
+                    Some(config),
+                ))
+            }
+        };
+        let native_format = converter.native_format();
 
-    //usando GDI
-    //La mayoria de funciones de la api de windows son inseguras
-    unsafe {
-        //let handler_time = Instant::now();
-
-        //Obtener resolucion
-        let width = GetSystemMetrics(SM_CXSCREEN);
-        let height = GetSystemMetrics(SM_CYSCREEN);
-        let xscreen = GetSystemMetrics(SM_XVIRTUALSCREEN);
-        let yscreen = GetSystemMetrics(SM_YVIRTUALSCREEN);
-
-        //Obtener un handler del device context de la pantalla completa
-        let window = GetDesktopWindow();
-        let screen = GetDC(window);
-
-        //Iniciar una variable que sera una copia de la pantalla (Para no modificar el original en pasos siguientes)
-        let screen_copy = CreateCompatibleDC(screen);
-
-        //Crear un bitmap para alojar lo que contenga la copia de la pantalla
-        //Aca me equivoque anteriormente, puse sobre crear un bitmap para la copia de la pantalla, pero la copia aun no tenia datos
-        let mut bitmap = CreateCompatibleBitmap(screen, width, height);
-
-        //Unir el bitmap con la copia de la pantalla  y luego copiar el contenido de la pantalla a la copia
-        SelectObject(screen_copy, bitmap);
-        SetStretchBltMode(screen_copy, COLORONCOLOR);
-        StretchBlt(
-            screen_copy,
-            0,
-            0,
+        Logger::log(
+            format!("Connected to the X server, screen {screen_number} {width}x{height} depth {depth}"),
+            LoggerLevel::Debug,
+            Some(config.clone()),
+        );
+
+        let screen = Rect {
+            x: 0,
+            y: 0,
             width,
             height,
+        };
+        let mut window = None;
+        let area = match &options.target {
+            CaptureTarget::Screen => screen,
+            CaptureTarget::Window(selector) => {
+                let found = match crate::window::find_window(&conn, root, selector) {
+                    Ok(Some(a)) => a,
+                    Ok(None) => {
+                        return Err(GabinatorError::newCapture(
+                            format!("There is no window matching {selector:?}"),
+                            LoggerLevel::Error,
+                            Some(config),
+                        ))
+                    }
+                    Err(a) => {
+                        return Err(GabinatorError::newCapture(
+                            format!("Not able to search the windows: {a}"),
+                            LoggerLevel::Error,
+                            Some(config),
+                        ))
+                    }
+                };
+                Logger::log(
+                    format!("Capturing window 0x{:x}", xcb::Xid::resource_id(&found)),
+                    LoggerLevel::Debug,
+                    Some(config.clone()),
+                );
+                window = Some(found);
+                //The real area is computed before every frame
+                screen
+            }
+            CaptureTarget::Region(region) => match region.intersect(&screen) {
+                Some(a) => a,
+                None => {
+                    return Err(GabinatorError::newCapture(
+                        format!("The region {region:?} is outside of the screen"),
+                        LoggerLevel::Error,
+                        Some(config),
+                    ))
+                }
+            },
+            CaptureTarget::Monitor(selector) => {
+                let monitors = match query_monitors(&conn, root) {
+                    Ok(a) => a,
+                    Err(a) => {
+                        return Err(GabinatorError::newCapture(
+                            format!("Not able to query the RandR monitors: {a}"),
+                            LoggerLevel::Error,
+                            Some(config),
+                        ))
+                    }
+                };
+                match find_monitor(&monitors, selector) {
+                    Some(a) => {
+                        Logger::log(
+                            format!("Capturing monitor {a}"),
+                            LoggerLevel::Debug,
+                            Some(config.clone()),
+                        );
+                        a.area
+                    }
+                    None => {
+                        return Err(GabinatorError::newCapture(
+                            format!("There is no active monitor called {selector}"),
+                            LoggerLevel::Error,
+                            Some(config),
+                        ))
+                    }
+                }
+            }
+        };
+
+        let backend = Self::start_backend(&conn, width, height, &layout, &config);
+
+        let mut options = options;
+        if options.composite && window.is_some() && !conn.active_extensions().any(|a| a == xcb::Extension::Composite) {
+            Logger::log(
+                "XComposite is not available, covered windows will not be captured correctly".to_string(),
+                LoggerLevel::Warning,
+                Some(config.clone()),
+            );
+            options.composite = false;
+        }
+        if options.cursor
+            && (!conn.active_extensions().any(|a| a == xcb::Extension::XFixes)
+                || crate::cursor::init_extension(&conn).is_err())
+        {
+            Logger::log(
+                "XFixes is not available, the cursor will not be drawn".to_string(),
+                LoggerLevel::Warning,
+                Some(config.clone()),
+            );
+            options.cursor = false;
+        }
+        if options.damage {
+            let available = conn.active_extensions().any(|a| a == xcb::Extension::Damage)
+                && conn.active_extensions().any(|a| a == xcb::Extension::XFixes);
+            if !available || crate::damage::init_extensions(&conn).is_err() {
+                Logger::log(
+                    "XDamage is not available, every frame will be sent".to_string(),
+                    LoggerLevel::Warning,
+                    Some(config.clone()),
+                );
+                options.damage = false;
+            }
+        }
+
+        Ok(ScreenCapturer {
+            conn,
+            root,
+            area,
+            converter,
+            native_format,
+            pixels: Vec::new(),
+            backend,
+            options,
             screen,
-            xscreen,
-            yscreen,
+            window,
+            pixmap: None,
+            window_origin: (0, 0),
+            cursor: None,
+            damage: None,
+            force_frame: true,
+            last_area: None,
+            dirty: Vec::new(),
+            region_config: config.get("region").cloned(),
+            region_checked: std::time::Instant::now(),
+            config,
+        })
+    }
+
+    //Tries to use MIT-SHM, falling back to GetImage if the extension is missing or the display is remote
+    fn start_backend(
+        conn: &xcb::Connection,
+        width: u16,
+        height: u16,
+        layout: &PixelLayout,
+        config: &HashMap<String, String>,
+    ) -> GrabBackend {
+        if !conn.active_extensions().any(|a| a == xcb::Extension::Shm) {
+            Logger::log(
+                "MIT-SHM is not available, using GetImage".to_string(),
+                LoggerLevel::Info,
+                Some(config.clone()),
+            );
+            return GrabBackend::GetImage;
+        }
+        if !is_local_display() {
+            Logger::log(
+                "The display is remote, using GetImage".to_string(),
+                LoggerLevel::Info,
+                Some(config.clone()),
+            );
+            return GrabBackend::GetImage;
+        }
+
+        match ShmSegment::new(conn, layout.stride(width as u32) * height as usize) {
+            Ok(a) => {
+                Logger::log(
+                    "Using MIT-SHM capture".to_string(),
+                    LoggerLevel::Debug,
+                    Some(config.clone()),
+                );
+                GrabBackend::Shm(a)
+            }
+            Err(a) => {
+                Logger::log(
+                    format!("Not able to set up MIT-SHM ({a}), using GetImage"),
+                    LoggerLevel::Warning,
+                    Some(config.clone()),
+                );
+                GrabBackend::GetImage
+            }
+        }
+    }
+
+    //Opens a new connection, used when the X server went away
+    fn reconnect(&mut self) -> Result<(), GabinatorError> {
+        //Dont hammer the server while it is restarting
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        *self = Self::new(self.options.clone())?;
+        Ok(())
+    }
+
+    //Follows the "region" key of the config, so the rectangle can be moved without restarting
+    fn refresh_region(&mut self) {
+        if !matches!(self.options.target, CaptureTarget::Region(_))
+            || self.region_checked.elapsed() < std::time::Duration::from_secs(1)
+        {
+            return;
+        }
+        self.region_checked = std::time::Instant::now();
+
+        let value = Logger::get_config_content().get("region").cloned();
+        if value == self.region_config {
+            return;
+        }
+        self.region_config = value.clone();
+        let region = match value.unwrap_or_default().parse::<Rect>() {
+            Ok(a) => a,
+            Err(a) => {
+                GabinatorError::newCapture(
+                    format!("Ignoring the new region: {a}"),
+                    LoggerLevel::Warning,
+                    Some(self.config.clone()),
+                );
+                return;
+            }
+        };
+        match region.intersect(&self.screen) {
+            Some(a) => {
+                Logger::log(
+                    format!("Region changed to {a:?}"),
+                    LoggerLevel::Debug,
+                    Some(self.config.clone()),
+                );
+                self.area = a;
+                self.options.target = CaptureTarget::Region(region);
+            }
+            None => {
+                GabinatorError::newCapture(
+                    format!("Ignoring the new region {region:?}, it is outside of the screen"),
+                    LoggerLevel::Warning,
+                    Some(self.config.clone()),
+                );
+            }
+        }
+    }
+
+    //Moves the capture area to where the followed window is now
+    fn refresh_window(&mut self) -> Result<(), GabinatorError> {
+        let selector = match (&self.options.target, self.window) {
+            (CaptureTarget::Window(a), Some(_)) => a.clone(),
+            _ => return Ok(()),
+        };
+
+        let mut area = crate::window::window_area(&self.conn, self.root, self.window.unwrap());
+        if let Err(xcb::Error::Protocol(_)) = area {
+            //The window was destroyed, the selector may match a new one (a restarted program)
+            match crate::window::find_window(&self.conn, self.root, &selector) {
+                Ok(Some(a)) => {
+                    Logger::log(
+                        format!("The window was replaced by 0x{:x}", xcb::Xid::resource_id(&a)),
+                        LoggerLevel::Debug,
+                        Some(self.config.clone()),
+                    );
+                    self.window = Some(a);
+                    area = crate::window::window_area(&self.conn, self.root, a);
+                }
+                Ok(None) => {
+                    return Err(GabinatorError::newCapture(
+                        format!("The window {selector:?} does not exist anymore"),
+                        LoggerLevel::Warning,
+                        Some(self.config.clone()),
+                    ))
+                }
+                Err(a) => area = Err(a),
+            }
+        }
+
+        let area = match area {
+            Ok(a) => a,
+            Err(a) => {
+                return Err(GabinatorError::newCapture(
+                    format!("Not able to get the window geometry: {a}"),
+                    LoggerLevel::Error,
+                    Some(self.config.clone()),
+                ))
+            }
+        };
+        if self.options.composite {
+            match self.name_pixmap(area.width, area.height) {
+                //The pixmap is never bigger than the shared memory segment
+                Ok(_) => {
+                    self.window_origin = (area.x, area.y);
+                    self.area = Rect {
+                        x: 0,
+                        y: 0,
+                        width: area.width.min(self.screen.width),
+                        height: area.height.min(self.screen.height),
+                    };
+                    return Ok(());
+                }
+                //Unmapped windows dont have a pixmap, use whatever is on the screen
+                Err(a) => {
+                    Logger::log(
+                        format!("Not able to get the window pixmap ({a:?}), capturing the screen area instead"),
+                        LoggerLevel::Debug,
+                        Some(self.config.clone()),
+                    );
+                    self.free_pixmap();
+                }
+            }
+        }
+
+        match area.intersect(&self.screen) {
+            Some(a) => {
+                self.area = a;
+                Ok(())
+            }
+            None => Err(GabinatorError::newCapture(
+                "The window is outside of the screen",
+                LoggerLevel::Warning,
+                Some(self.config.clone()),
+            )),
+        }
+    }
+
+    //Redirects the followed window and names its pixmap, again every time the window is resized
+    fn name_pixmap(&mut self, width: u16, height: u16) -> xcb::ProtocolResult<()> {
+        use xcb::composite;
+
+        let window = self.window.unwrap();
+        if let Some(pixmap) = &self.pixmap {
+            if pixmap.window == window && pixmap.width == width && pixmap.height == height {
+                return Ok(());
+            }
+        }
+        let redirect = self.pixmap.as_ref().map(|a| a.window != window).unwrap_or(true);
+        self.free_pixmap();
+
+        if redirect {
+            self.conn.send_and_check_request(&composite::RedirectWindow {
+                window,
+                update: composite::Redirect::Automatic,
+            })?;
+        }
+        let pixmap: xcb::x::Pixmap = self.conn.generate_id();
+        self.conn.send_and_check_request(&composite::NameWindowPixmap { window, pixmap })?;
+        self.pixmap = Some(CompositePixmap {
+            window,
+            pixmap,
             width,
             height,
-            SRCCOPY,
-        );
+        });
+        Ok(())
+    }
 
-        //Crear un buffer que contendra el bitmap del header bitmap
-        let buffer_size = width * height * 3; //4 serian los valores R G B A por cada pixel
-        let mut buffer = vec![0u8; buffer_size as usize];
-
-        //Configuraciones
-        let mut bitmap_info = BITMAPINFO {
-            bmiHeader: BITMAPINFOHEADER {
-                biSize: size_of::<BITMAPINFOHEADER>() as u32,
-                biWidth: width,
-                biHeight: -height,
-                biPlanes: 1,
-                biBitCount: 24,
-                biSizeImage: buffer_size as u32,
-                biCompression: 0,
-                ..Default::default()
+    fn free_pixmap(&mut self) {
+        if let Some(a) = self.pixmap.take() {
+            self.conn.send_request(&xcb::x::FreePixmap { pixmap: a.pixmap });
+        }
+    }
+
+    //Returns true if the captured area changed and fills the dirty rectangles
+    fn poll_damage(&mut self) -> bool {
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width: self.area.width,
+            height: self.area.height,
+        };
+        self.dirty.clear();
+        let area_changed = self.last_area != Some(self.area);
+        self.last_area = Some(self.area);
+        if !self.options.damage {
+            self.dirty.push(full);
+            return true;
+        }
+
+        //Damage is reported for windows, so a composite pixmap is tracked through its window
+        let drawable = match &self.pixmap {
+            Some(a) => xcb::x::Drawable::Window(a.window),
+            None => xcb::x::Drawable::Window(self.root),
+        };
+        if self.damage.as_ref().map(|a| a.drawable != drawable).unwrap_or(true) {
+            if let Some(a) = self.damage.take() {
+                a.destroy(&self.conn);
+            }
+            match crate::damage::DamageTracker::new(&self.conn, drawable) {
+                Ok(a) => self.damage = Some(a),
+                Err(a) => {
+                    GabinatorError::newCapture(
+                        format!("Not able to track damage ({a}), every frame will be sent"),
+                        LoggerLevel::Warning,
+                        Some(self.config.clone()),
+                    );
+                    self.options.damage = false;
+                }
+            }
+            self.force_frame = true;
+        }
+
+        let damaged = match self.damage.as_mut().map(|a| a.poll(&self.conn)) {
+            Some(Ok(a)) => a,
+            Some(Err(a)) => {
+                GabinatorError::newCapture(
+                    format!("Error reading damage ({a}), sending the whole frame"),
+                    LoggerLevel::Warning,
+                    Some(self.config.clone()),
+                );
+                self.force_frame = true;
+                false
+            }
+            None => false,
+        };
+
+        if self.force_frame || area_changed {
+            self.force_frame = false;
+            self.dirty.push(full);
+            return true;
+        }
+        if !damaged {
+            return false;
+        }
+
+        //Move the rectangles from drawable coordinates to frame coordinates
+        let area = self.area;
+        if let Some(tracker) = &self.damage {
+            self.dirty.extend(tracker.dirty_rects().iter().filter_map(|a| {
+                a.intersect(&area).map(|b| Rect {
+                    x: b.x - area.x,
+                    y: b.y - area.y,
+                    width: b.width,
+                    height: b.height,
+                })
+            }));
+        }
+        !self.dirty.is_empty()
+    }
+
+    //Root coordinates of the top left corner of the frame
+    fn origin(&self) -> (i32, i32) {
+        match &self.pixmap {
+            Some(_) => (self.window_origin.0 as i32, self.window_origin.1 as i32),
+            None => (self.area.x as i32, self.area.y as i32),
+        }
+    }
+
+    //Reads the pointer image and returns true if it moved or changed its shape inside the frame
+    fn poll_cursor(&mut self) -> bool {
+        if !self.options.cursor {
+            return false;
+        }
+        let cursor = match crate::cursor::cursor_image(&self.conn) {
+            Ok(a) => a,
+            Err(a) => {
+                GabinatorError::newCapture(
+                    format!("Not able to read the cursor: {a}"),
+                    LoggerLevel::Debug,
+                    Some(self.config.clone()),
+                );
+                return false;
+            }
+        };
+
+        let mut changed = false;
+        let previous = self.cursor.as_ref().map(|a| (a.bounds(), a.serial));
+        if previous != Some((cursor.bounds(), cursor.serial)) {
+            //The old position has to be repainted too
+            let (origin_x, origin_y) = self.origin();
+            let frame = Rect {
+                x: 0,
+                y: 0,
+                width: self.area.width,
+                height: self.area.height,
+            };
+            let bounds = [previous.map(|a| a.0), Some(cursor.bounds())];
+            for a in bounds.into_iter().flatten() {
+                let moved = Rect {
+                    x: (a.x as i32 - origin_x) as i16,
+                    y: (a.y as i32 - origin_y) as i16,
+                    width: a.width,
+                    height: a.height,
+                };
+                if let Some(b) = moved.intersect(&frame) {
+                    self.dirty.push(b);
+                    changed = true;
+                }
+            }
+        }
+        self.cursor = Some(cursor);
+        changed
+    }
+
+    //Drawable the area refers to
+    fn drawable(&self) -> xcb::x::Drawable {
+        match &self.pixmap {
+            Some(a) => xcb::x::Drawable::Pixmap(a.pixmap),
+            None => xcb::x::Drawable::Window(self.root),
+        }
+    }
+
+    //Captures the screen, None if nothing changed since the last frame
+    fn capture(&mut self) -> Result<Option<Frame<'_>>, GabinatorError> {
+        if let Err(a) = self.conn.has_error() {
+            GabinatorError::newCapture(
+                format!("Lost the X connection ({a}), reconnecting..."),
+                LoggerLevel::Warning,
+                Some(self.config.clone()),
+            );
+            self.reconnect()?;
+        }
+        self.refresh_region();
+        self.refresh_window()?;
+        let damaged = self.poll_damage();
+        if !self.poll_cursor() && !damaged {
+            //Nothing to do, dont spin while the screen is idle
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            return Ok(None);
+        }
+
+        let in_segment = match self.grab() {
+            Ok(a) => a,
+            Err(xcb::Error::Connection(a)) => {
+                GabinatorError::newCapture(
+                    format!("Lost the X connection ({a}), reconnecting..."),
+                    LoggerLevel::Warning,
+                    Some(self.config.clone()),
+                );
+                self.reconnect()?;
+                match self.grab() {
+                    Ok(a) => a,
+                    Err(a) => {
+                        return Err(GabinatorError::newCapture(
+                            format!("Error capturing the screen after reconnecting: {a}"),
+                            LoggerLevel::Error,
+                            Some(self.config.clone()),
+                        ))
+                    }
+                }
+            }
+            Err(a) => {
+                return Err(GabinatorError::newCapture(
+                    format!("Error capturing the screen: {a}"),
+                    LoggerLevel::Error,
+                    Some(self.config.clone()),
+                ))
+            }
+        };
+
+        let width = self.area.width as u32;
+        let height = self.area.height as u32;
+        let (format, pitch) = match self.native_format {
+            Some(a) => (a, self.converter.layout().stride(width)),
+            None => (PixelFormat::Rgba, width as usize * 4),
+        };
+        let origin = self.origin();
+        let pixels = match (&mut self.backend, in_segment) {
+            (GrabBackend::Shm(segment), true) => segment.data_mut(pitch * height as usize),
+            _ => self.pixels.as_mut_slice(),
+        };
+        if let Some(cursor) = &self.cursor {
+            crate::cursor::blend_cursor(pixels, width, height, pitch, format, origin, cursor);
+        }
+
+        Ok(Some(Frame {
+            data: pixels,
+            width,
+            height,
+            pitch,
+            format,
+        }))
+    }
+
+    //Gets the captured area, returns true if the pixels were left in the MIT-SHM segment
+    //and false if they are in the frame buffer, converted to RGBA when the format is not native
+    fn grab(&mut self) -> xcb::Result<bool> {
+        use xcb::x::{self, GetImage};
+
+        if let GrabBackend::Shm(segment) = &self.backend {
+            let cookie_get_image = self.conn.send_request(&xcb::shm::GetImage {
+                drawable: self.drawable(),
+                x: self.area.x,
+                y: self.area.y,
+                width: self.area.width,
+                height: self.area.height,
+                plane_mask: u32::MAX,
+                format: x::ImageFormat::ZPixmap as u8,
+                shmseg: segment.seg,
+                offset: 0,
+            });
+            match self.conn.wait_for_reply(cookie_get_image) {
+                Ok(_) => {
+                    if self.native_format.is_some() {
+                        return Ok(true);
+                    }
+                    let (width, height) = (self.area.width as u32, self.area.height as u32);
+                    self.converter
+                        .convert_into(segment.data(segment.size), width, height, &mut self.pixels);
+                    return Ok(false);
+                }
+                Err(xcb::Error::Protocol(a)) => {
+                    GabinatorError::newCapture(
+                        format!("MIT-SHM capture failed ({a:?}), falling back to GetImage"),
+                        LoggerLevel::Warning,
+                        Some(self.config.clone()),
+                    );
+                    self.backend = GrabBackend::GetImage;
+                }
+                Err(a) => return Err(a),
+            }
+        }
+
+        let cookie_get_image = self.conn.send_request(&GetImage {
+            format: x::ImageFormat::ZPixmap,
+            drawable: self.drawable(),
+            x: self.area.x,
+            y: self.area.y,
+            width: self.area.width,
+            height: self.area.height,
+            plane_mask: u32::MAX,
+        });
+        let image_reply = self.conn.wait_for_reply(cookie_get_image)?;
+        let (width, height) = (self.area.width as u32, self.area.height as u32);
+        if self.native_format.is_some() {
+            //Short replies leave the rest of the frame black
+            let len = self.converter.layout().stride(width) * height as usize;
+            let data = &image_reply.data()[..image_reply.data().len().min(len)];
+            self.pixels.clear();
+            self.pixels.extend_from_slice(data);
+            self.pixels.resize(len, 0);
+        } else {
+            self.converter
+                .convert_into(image_reply.data(), width, height, &mut self.pixels);
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl FrameSource for ScreenCapturer {
+    fn next_frame(&mut self) -> Result<Option<Frame<'_>>, GabinatorError> {
+        self.capture()
+    }
+
+    //Sends the next frame even if nothing changed, for example when a new client connects
+    fn invalidate(&mut self) {
+        self.force_frame = true;
+    }
+
+    //Parts of the last frame that changed, relative to the captured area
+    fn dirty_rects(&self) -> &[Rect] {
+        &self.dirty
+    }
+
+    fn desktop_origin(&self) -> Option<(i32, i32)> {
+        Some(self.origin())
+    }
+}
+
+//GDI capturer, it keeps the same interface as the X11 one
+#[cfg(target_os = "windows")]
+pub struct ScreenCapturer {
+    //Last frame as GDI returns it, BGR with rows padded to 4 bytes
+    pixels: Vec<u8>,
+    dirty: Vec<Rect>,
+}
+
+//RandR only exists on X11
+#[cfg(target_os = "windows")]
+pub fn list_monitors() -> Result<Vec<Monitor>, GabinatorError> {
+    Err(GabinatorError::newCapture(
+        "Listing monitors is only supported on X11",
+        LoggerLevel::Error,
+        None,
+    ))
+}
+
+#[cfg(target_os = "windows")]
+impl ScreenCapturer {
+    pub fn new(options: CaptureOptions) -> Result<Self, GabinatorError> {
+        if !matches!(options.target, CaptureTarget::Screen) {
+            return Err(GabinatorError::newCapture(
+                "Monitor, region and window selection are only supported on X11",
+                LoggerLevel::Error,
+                None,
+            ));
+        }
+        Ok(ScreenCapturer {
+            pixels: Vec::new(),
+            dirty: Vec::new(),
+        })
+    }
+
+    fn capture(&mut self) -> Result<Option<Frame<'_>>, GabinatorError> {
+        use image::{
+            codecs::{
+                jpeg::{self, JpegEncoder},
+                png::{PngDecoder, PngEncoder},
             },
-            ..Default::default()
-        };
-
-        //Extraer el bitmap
-        GetDIBits(
-            screen_copy,
-            bitmap,
-            0,
-            height as u32,
-            Some(buffer.as_mut_ptr().cast()),
-            &mut bitmap_info,
-            DIB_RGB_COLORS,
-        );
+            ColorType, DynamicImage, RgbImage, Rgba,
+        };
+        use std::{
+            error::Error,
+            fs::File,
+            io::{BufWriter, Cursor, Write},
+            time::Instant,
+            u32,
+        };
+
+        use image::{ExtendedColorType, ImageBuffer, ImageEncoder, ImageFormat, Rgb, RgbaImage};
+        use std::{
+            io::Read,
+            mem::size_of,
+            ptr::{null, null_mut},
+        };
+        use sysinfo::System;
+        use windows::{
+            core::HRESULT,
+            Win32::{
+                Graphics::Gdi::{
+                    BitBlt, CreateCompatibleBitmap, CreateCompatibleDC, GetDC, GetDIBits, SelectObject,
+                    SetStretchBltMode, StretchBlt, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, COLORONCOLOR,
+                    DIB_RGB_COLORS, SRCCOPY,
+                },
+                UI::WindowsAndMessaging::{
+                    GetDesktopWindow, GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN, SM_XVIRTUALSCREEN,
+                    SM_YVIRTUALSCREEN,
+                },
+            },
+        };
+
+        //usando GDI
+        //La mayoria de funciones de la api de windows son inseguras
+        unsafe {
+            //let handler_time = Instant::now();
+
+            //Obtener resolucion
+            let width = GetSystemMetrics(SM_CXSCREEN);
+            let height = GetSystemMetrics(SM_CYSCREEN);
+            self.dirty = vec![Rect {
+                x: 0,
+                y: 0,
+                width: width as u16,
+                height: height as u16,
+            }];
+            let xscreen = GetSystemMetrics(SM_XVIRTUALSCREEN);
+            let yscreen = GetSystemMetrics(SM_YVIRTUALSCREEN);
+
+            //Obtener un handler del device context de la pantalla completa
+            let window = GetDesktopWindow();
+            let screen = GetDC(window);
+
+            //Iniciar una variable que sera una copia de la pantalla (Para no modificar el original en pasos siguientes)
+            let screen_copy = CreateCompatibleDC(screen);
 
-        //De RGB a BGR, si no se pone asi, el rojo y el azul se ven intercambiados en la imagen final
-        for px in buffer.chunks_exact_mut(3) {
-            px.swap(0, 2);
+            //Crear un bitmap para alojar lo que contenga la copia de la pantalla
+            //Aca me equivoque anteriormente, puse sobre crear un bitmap para la copia de la pantalla, pero la copia aun no tenia datos
+            let mut bitmap = CreateCompatibleBitmap(screen, width, height);
+
+            //Unir el bitmap con la copia de la pantalla  y luego copiar el contenido de la pantalla a la copia
+            SelectObject(screen_copy, bitmap);
+            SetStretchBltMode(screen_copy, COLORONCOLOR);
+            StretchBlt(
+                screen_copy,
+                0,
+                0,
+                width,
+                height,
+                screen,
+                xscreen,
+                yscreen,
+                width,
+                height,
+                SRCCOPY,
+            );
+
+            //Crear un buffer que contendra el bitmap del header bitmap
+            //Las filas de un DIB de 24 bits se rellenan hasta multiplos de 4 bytes
+            let pitch = (width * 3 + 3) & !3;
+            let buffer_size = pitch * height;
+            self.pixels.resize(buffer_size as usize, 0);
+
+            //Configuraciones
+            let mut bitmap_info = BITMAPINFO {
+                bmiHeader: BITMAPINFOHEADER {
+                    biSize: size_of::<BITMAPINFOHEADER>() as u32,
+                    biWidth: width,
+                    biHeight: -height,
+                    biPlanes: 1,
+                    biBitCount: 24,
+                    biSizeImage: buffer_size as u32,
+                    biCompression: 0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            //Extraer el bitmap
+            GetDIBits(
+                screen_copy,
+                bitmap,
+                0,
+                height as u32,
+                Some(self.pixels.as_mut_ptr().cast()),
+                &mut bitmap_info,
+                DIB_RGB_COLORS,
+            );
+
+            //GDI entrega BGR, el encoder lo lee directamente
+            return Ok(Some(Frame {
+                data: &self.pixels,
+                width: width as u32,
+                height: height as u32,
+                pitch: pitch as usize,
+                format: PixelFormat::Bgr,
+            }));
         }
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl FrameSource for ScreenCapturer {
+    fn next_frame(&mut self) -> Result<Option<Frame<'_>>, GabinatorError> {
+        self.capture()
+    }
+
+    //There is no damage tracking on GDI, every frame is sent anyway
+    fn invalidate(&mut self) {}
+
+    fn dirty_rects(&self) -> &[Rect] {
+        &self.dirty
+    }
 
-        let encoding_time = Instant::now();
-        let image = RgbImage::from_raw(width as u32, height as u32, buffer)
-            .expect("Error convirtiendo en formato RGBA");
-        let jpeg = turbojpeg::compress_image(&image, quality.into(), turbojpeg::Subsamp::Sub2x2).unwrap();
-        return Ok(jpeg.as_ref().to_vec());
+    //GDI always captures the whole screen
+    fn desktop_origin(&self) -> Option<(i32, i32)> {
+        Some((0, 0))
     }
 }
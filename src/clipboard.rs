@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+#[cfg(target_os = "linux")]
+use std::sync::mpsc::TryRecvError;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use xcb::{x, xfixes};
+
+use crate::error::{GabinatorError, LoggerLevel};
+#[cfg(target_os = "linux")]
+use crate::error::Logger;
+use crate::message::{ClipboardContent, ClipboardData};
+#[cfg(target_os = "linux")]
+use crate::message::{Selection, MAX_CONTENT};
+
+//How often the selection thread looks for X events and for content from the receiver
+#[cfg(target_os = "linux")]
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+//Keeps the selections of the desktop and the receiver the same, the X side runs in its own thread
+pub struct Clipboard {
+    //Content the receiver copied, the thread becomes the owner of the selection for it
+    to_desktop: Sender<ClipboardContent>,
+    //Content copied on the desktop, for the receiver
+    from_desktop: Receiver<ClipboardContent>,
+    //PNG images go both ways too, else only text
+    images: bool,
+}
+
+impl Clipboard {
+    pub fn new(images: bool, config: &HashMap<String, String>) -> Result<Self, GabinatorError> {
+        let selections = Selections::new(images, config)?;
+        let (to_desktop, desktop_receiver) = channel();
+        let (desktop_sender, from_desktop) = channel();
+        std::thread::spawn(move || selections.run(desktop_receiver, desktop_sender));
+        Ok(Clipboard {
+            to_desktop,
+            from_desktop,
+            images,
+        })
+    }
+
+    //Gives the desktop what the receiver copied
+    pub fn set(&self, content: ClipboardContent) {
+        if !self.images && matches!(content.data, ClipboardData::Png(_)) {
+            return;
+        }
+        let _ = self.to_desktop.send(content);
+    }
+
+    //Something copied on the desktop since the last call
+    pub fn changed(&self) -> Option<ClipboardContent> {
+        self.from_desktop.try_recv().ok()
+    }
+}
+
+#[cfg(target_os = "linux")]
+struct Atoms {
+    clipboard: x::Atom,
+    targets: x::Atom,
+    utf8_string: x::Atom,
+    text: x::Atom,
+    png: x::Atom,
+    incr: x::Atom,
+}
+
+//Owner of the selections for the receiver and reader of the ones of the desktop.
+//Content is asked for in two steps, first the TARGETS it can be converted to and then the best of them
+#[cfg(target_os = "linux")]
+struct Selections {
+    conn: xcb::Connection,
+    //Hidden window that owns the selections and gets the converted content
+    window: x::Window,
+    atoms: Atoms,
+    images: bool,
+    //What the receiver copied, while this side owns the selection. CLIPBOARD first and then PRIMARY
+    owned: [Option<ClipboardData>; 2],
+    //Last content of each selection that went either way, so it does not bounce between the sides
+    last: [Option<ClipboardData>; 2],
+    config: HashMap<String, String>,
+}
+
+#[cfg(target_os = "linux")]
+fn intern_atom(conn: &xcb::Connection, name: &str) -> xcb::Result<x::Atom> {
+    let reply = conn.wait_for_reply(conn.send_request(&x::InternAtom {
+        only_if_exists: false,
+        name: name.as_bytes(),
+    }))?;
+    Ok(reply.atom())
+}
+
+#[cfg(target_os = "linux")]
+fn index(selection: Selection) -> usize {
+    match selection {
+        Selection::Clipboard => 0,
+        Selection::Primary => 1,
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Selections {
+    fn new(images: bool, config: &HashMap<String, String>) -> Result<Self, GabinatorError> {
+        let error = |a: String| GabinatorError::newCapture(a, LoggerLevel::Error, Some(config.clone()));
+        let (conn, screen_number) = xcb::Connection::connect_with_extensions(None, &[xcb::Extension::XFixes], &[])
+            .map_err(|a| error(format!("Not able to connect to the X server for the clipboard: {a}")))?;
+        if crate::cursor::init_extension(&conn).is_err() {
+            return Err(error("XFixes is not available, the clipboard can not be followed".to_string()));
+        }
+        let root = match conn.get_setup().roots().nth(screen_number as usize) {
+            Some(a) => a.root(),
+            None => return Err(error("The X server has no screen for the clipboard".to_string())),
+        };
+        let result = (|| -> xcb::Result<(x::Window, Atoms)> {
+            let atoms = Atoms {
+                clipboard: intern_atom(&conn, "CLIPBOARD")?,
+                targets: intern_atom(&conn, "TARGETS")?,
+                utf8_string: intern_atom(&conn, "UTF8_STRING")?,
+                text: intern_atom(&conn, "TEXT")?,
+                png: intern_atom(&conn, "image/png")?,
+                incr: intern_atom(&conn, "INCR")?,
+            };
+            let window = conn.generate_id();
+            conn.send_and_check_request(&x::CreateWindow {
+                depth: x::COPY_FROM_PARENT as u8,
+                wid: window,
+                parent: root,
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+                border_width: 0,
+                class: x::WindowClass::InputOnly,
+                visual: x::COPY_FROM_PARENT,
+                value_list: &[],
+            })?;
+            for selection in [atoms.clipboard, x::ATOM_PRIMARY] {
+                conn.send_and_check_request(&xfixes::SelectSelectionInput {
+                    window,
+                    selection,
+                    event_mask: xfixes::SelectionEventMask::SET_SELECTION_OWNER,
+                })?;
+            }
+            Ok((window, atoms))
+        })();
+        let (window, atoms) = result.map_err(|a| error(format!("Not able to follow the clipboard: {a}")))?;
+        Ok(Selections {
+            conn,
+            window,
+            atoms,
+            images,
+            owned: [None, None],
+            last: [None, None],
+            config: config.clone(),
+        })
+    }
+
+    fn selection(&self, atom: x::Atom) -> Option<Selection> {
+        match atom {
+            a if a == self.atoms.clipboard => Some(Selection::Clipboard),
+            x::ATOM_PRIMARY => Some(Selection::Primary),
+            _ => None,
+        }
+    }
+
+    fn atom(&self, selection: Selection) -> x::Atom {
+        match selection {
+            Selection::Clipboard => self.atoms.clipboard,
+            Selection::Primary => x::ATOM_PRIMARY,
+        }
+    }
+
+    //Runs until the Clipboard is dropped or the X server goes away
+    fn run(mut self, receiver: Receiver<ClipboardContent>, sender: Sender<ClipboardContent>) {
+        loop {
+            loop {
+                match receiver.try_recv() {
+                    Ok(a) => self.own(a),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+            loop {
+                match self.conn.poll_for_event() {
+                    Ok(Some(event)) => {
+                        if let Some(a) = self.handle(event) {
+                            if sender.send(a).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(xcb::Error::Connection(a)) => {
+                        GabinatorError::newCapture(
+                            format!("Lost the X server, the clipboard is not synchronized anymore: {a}"),
+                            LoggerLevel::Error,
+                            Some(self.config.clone()),
+                        );
+                        return;
+                    }
+                    Err(a) => Logger::log(
+                        format!("Clipboard request failed: {a}"),
+                        LoggerLevel::Debug,
+                        Some(self.config.clone()),
+                    ),
+                }
+            }
+            let _ = self.conn.flush();
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    //Becomes the owner of the selection with what the receiver copied
+    fn own(&mut self, content: ClipboardContent) {
+        let index = index(content.selection);
+        if self.last[index].as_ref() == Some(&content.data) {
+            return;
+        }
+        let result = self.conn.send_and_check_request(&x::SetSelectionOwner {
+            owner: self.window,
+            selection: self.atom(content.selection),
+            time: x::CURRENT_TIME,
+        });
+        if let Err(a) = result {
+            GabinatorError::newCapture(
+                format!("Not able to own the {:?} selection: {a}", content.selection),
+                LoggerLevel::Warning,
+                Some(self.config.clone()),
+            );
+            return;
+        }
+        self.owned[index] = Some(content.data.clone());
+        self.last[index] = Some(content.data);
+    }
+
+    //New content of the desktop for the receiver, if the event finished reading some
+    fn handle(&mut self, event: xcb::Event) -> Option<ClipboardContent> {
+        match event {
+            //Someone copied, the first step is asking what it can be converted to
+            xcb::Event::XFixes(xfixes::Event::SelectionNotify(a)) => {
+                if a.owner() != self.window && a.owner() != x::WINDOW_NONE {
+                    self.convert(a.selection(), self.atoms.targets);
+                }
+                None
+            }
+            xcb::Event::X(x::Event::SelectionNotify(a)) => self.converted(a.selection(), a.target(), a.property()),
+            xcb::Event::X(x::Event::SelectionRequest(a)) => {
+                self.serve(&a);
+                None
+            }
+            xcb::Event::X(x::Event::SelectionClear(a)) => {
+                if let Some(selection) = self.selection(a.selection()) {
+                    self.owned[index(selection)] = None;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    //The content goes to a property of the window named like the selection
+    fn convert(&self, selection: x::Atom, target: x::Atom) {
+        self.conn.send_request(&x::ConvertSelection {
+            requestor: self.window,
+            selection,
+            target,
+            property: selection,
+            time: x::CURRENT_TIME,
+        });
+    }
+
+    fn converted(&mut self, atom: x::Atom, target: x::Atom, property: x::Atom) -> Option<ClipboardContent> {
+        let selection = self.selection(atom)?;
+        //The owner does not know TARGETS, plain text is tried as UTF8_STRING and then as STRING for the oldest owners
+        if property == x::ATOM_NONE {
+            match target {
+                a if a == self.atoms.targets => self.convert(atom, self.atoms.utf8_string),
+                a if a == self.atoms.utf8_string => self.convert(atom, x::ATOM_STRING),
+                _ => {}
+            }
+            return None;
+        }
+        let reply = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&x::GetProperty {
+                delete: true,
+                window: self.window,
+                property,
+                //AnyPropertyType
+                r#type: x::ATOM_NONE,
+                long_offset: 0,
+                long_length: (MAX_CONTENT / 4) as u32,
+            }))
+            .ok()?;
+        //INCR transfers are not read, the owners use them for content bigger than what fits in one request
+        if reply.r#type() == self.atoms.incr || reply.bytes_after() > 0 {
+            Logger::log(
+                format!("The {selection:?} selection is too big to be synchronized"),
+                LoggerLevel::Warning,
+                Some(self.config.clone()),
+            );
+            return None;
+        }
+        let data = match target {
+            a if a == self.atoms.targets => {
+                let targets = reply.value::<x::Atom>();
+                let best = [
+                    Some(self.atoms.png).filter(|_| self.images),
+                    Some(self.atoms.utf8_string),
+                    Some(x::ATOM_STRING),
+                ]
+                .into_iter()
+                .flatten()
+                .find(|a| targets.contains(a));
+                if let Some(a) = best {
+                    self.convert(atom, a);
+                }
+                return None;
+            }
+            a if a == self.atoms.png => ClipboardData::Png(reply.value::<u8>().to_vec()),
+            a if a == self.atoms.utf8_string => {
+                ClipboardData::Text(String::from_utf8_lossy(reply.value::<u8>()).into_owned())
+            }
+            //STRING is Latin-1
+            x::ATOM_STRING => ClipboardData::Text(reply.value::<u8>().iter().map(|a| *a as char).collect()),
+            _ => return None,
+        };
+        let index = index(selection);
+        if self.last[index].as_ref() == Some(&data) {
+            return None;
+        }
+        self.last[index] = Some(data.clone());
+        Some(ClipboardContent { selection, data })
+    }
+
+    //Answers a program that pastes what the receiver copied
+    fn serve(&self, request: &x::SelectionRequestEvent) {
+        //Old programs leave the property out, the target is used instead
+        let property = match request.property() {
+            x::ATOM_NONE => request.target(),
+            a => a,
+        };
+        let owned = self.selection(request.selection()).and_then(|a| self.owned[index(a)].as_ref());
+        let target = request.target();
+        let change = |kind: x::Atom, data: &[u8]| {
+            //Content that does not fit in one request would need INCR
+            let limit = self.conn.get_maximum_request_length() as usize * 4 - 32;
+            data.len() <= limit
+                && self
+                    .conn
+                    .send_and_check_request(&x::ChangeProperty {
+                        mode: x::PropMode::Replace,
+                        window: request.requestor(),
+                        property,
+                        r#type: kind,
+                        data,
+                    })
+                    .is_ok()
+        };
+        let stored = match owned {
+            Some(data) if target == self.atoms.targets => {
+                let targets = match data {
+                    ClipboardData::Text(_) => vec![
+                        self.atoms.targets,
+                        self.atoms.utf8_string,
+                        self.atoms.text,
+                        x::ATOM_STRING,
+                    ],
+                    ClipboardData::Png(_) => vec![self.atoms.targets, self.atoms.png],
+                };
+                self.conn
+                    .send_and_check_request(&x::ChangeProperty {
+                        mode: x::PropMode::Replace,
+                        window: request.requestor(),
+                        property,
+                        r#type: x::ATOM_ATOM,
+                        data: &targets,
+                    })
+                    .is_ok()
+            }
+            Some(ClipboardData::Text(a)) if target == self.atoms.utf8_string || target == self.atoms.text => {
+                change(self.atoms.utf8_string, a.as_bytes())
+            }
+            Some(ClipboardData::Text(a)) if target == x::ATOM_STRING => {
+                let latin1: Vec<u8> = a.chars().map(|a| if (a as u32) < 0x100 { a as u8 } else { b'?' }).collect();
+                change(x::ATOM_STRING, &latin1)
+            }
+            Some(ClipboardData::Png(a)) if target == self.atoms.png => change(self.atoms.png, a),
+            _ => false,
+        };
+        let event = x::SelectionNotifyEvent::new(
+            request.time(),
+            request.requestor(),
+            request.selection(),
+            target,
+            if stored { property } else { x::ATOM_NONE },
+        );
+        self.conn.send_request(&x::SendEvent {
+            propagate: false,
+            destination: x::SendEventDest::Window(request.requestor()),
+            event_mask: x::EventMask::empty(),
+            event: &event,
+        });
+    }
+}
+
+//Selections only exist on X11, on Windows the clipboard is not synchronized
+#[cfg(target_os = "windows")]
+struct Selections;
+
+#[cfg(target_os = "windows")]
+impl Selections {
+    fn new(_images: bool, config: &HashMap<String, String>) -> Result<Self, GabinatorError> {
+        Err(GabinatorError::newCapture(
+            "The clipboard is only synchronized on X11",
+            LoggerLevel::Warning,
+            Some(config.clone()),
+        ))
+    }
+
+    fn run(self, _receiver: Receiver<ClipboardContent>, _sender: Sender<ClipboardContent>) {}
+}
@@ -0,0 +1,333 @@
+//Conversion of ZPixmap images, as GetImage or MIT-SHM return them, to RGBA
+
+use crate::frame::PixelFormat;
+
+//Byte order of the pixels, the X server reports it in the setup
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteOrder {
+    LsbFirst,
+    MsbFirst,
+}
+
+//Everything needed to decode a ZPixmap image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelLayout {
+    pub bits_per_pixel: u8,
+    //Every scanline is padded to a multiple of this many bits
+    pub scanline_pad: u8,
+    pub byte_order: ByteOrder,
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32,
+}
+
+impl PixelLayout {
+    //Bytes between the start of two scanlines
+    pub fn stride(&self, width: u32) -> usize {
+        let pad = self.scanline_pad.max(8) as usize;
+        (width as usize * self.bits_per_pixel as usize).div_ceil(pad) * pad / 8
+    }
+}
+
+//One color channel, the lookup table maps the raw channel value to 0..=255
+struct Channel {
+    shift: u32,
+    mask: u32,
+    table: Vec<u8>,
+}
+
+impl Channel {
+    fn new(mask: u32) -> Self {
+        let shift = if mask == 0 { 0 } else { mask.trailing_zeros() };
+        let max = mask >> shift;
+        let table = (0..=max)
+            .map(|a| (a * 255 + max / 2).checked_div(max).unwrap_or(0) as u8)
+            .collect();
+        Channel { shift, mask, table }
+    }
+
+    fn get(&self, pixel: u32) -> u8 {
+        self.table[((pixel & self.mask) >> self.shift) as usize]
+    }
+}
+
+//Layouts that can be copied byte by byte, without looking at every channel
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FastPath {
+    //32 bits per pixel, 8 bits per channel, positions of R G B inside the 4 bytes
+    Bytes32(usize, usize, usize),
+    //24 bits per pixel, 8 bits per channel
+    Bytes24(usize, usize, usize),
+    None,
+}
+
+pub struct PixelConverter {
+    layout: PixelLayout,
+    red: Channel,
+    green: Channel,
+    blue: Channel,
+    fast_path: FastPath,
+}
+
+impl PixelConverter {
+    pub fn new(layout: PixelLayout) -> Result<Self, String> {
+        if ![8, 16, 24, 32].contains(&layout.bits_per_pixel) {
+            return Err(format!("{} bits per pixel is not supported", layout.bits_per_pixel));
+        }
+
+        //Visuals without masks (PseudoColor) are read as 3-3-2 like before
+        let mut layout = layout;
+        if layout.red_mask == 0 && layout.green_mask == 0 && layout.blue_mask == 0 {
+            layout.red_mask = 0xe0;
+            layout.green_mask = 0x1c;
+            layout.blue_mask = 0x03;
+        }
+
+        //Byte index of an 8 bit channel inside the pixel, if the channel is byte aligned.
+        //An empty mask is a channel that is always 0, it goes through the lookup tables
+        let byte_of = |mask: u32| -> Option<usize> {
+            if mask == 0 {
+                return None;
+            }
+            let bytes = layout.bits_per_pixel as usize / 8;
+            let shift = mask.trailing_zeros() as usize;
+            if mask >> shift != 0xff || !shift.is_multiple_of(8) {
+                return None;
+            }
+            match layout.byte_order {
+                ByteOrder::LsbFirst => Some(shift / 8),
+                ByteOrder::MsbFirst => Some(bytes - 1 - shift / 8),
+            }
+        };
+        let bytes = (
+            byte_of(layout.red_mask),
+            byte_of(layout.green_mask),
+            byte_of(layout.blue_mask),
+        );
+        let fast_path = match (layout.bits_per_pixel, bytes) {
+            (32, (Some(r), Some(g), Some(b))) => FastPath::Bytes32(r, g, b),
+            (24, (Some(r), Some(g), Some(b))) => FastPath::Bytes24(r, g, b),
+            _ => FastPath::None,
+        };
+
+        Ok(PixelConverter {
+            layout,
+            red: Channel::new(layout.red_mask),
+            green: Channel::new(layout.green_mask),
+            blue: Channel::new(layout.blue_mask),
+            fast_path,
+        })
+    }
+
+    pub fn layout(&self) -> PixelLayout {
+        self.layout
+    }
+
+    //Format the encoder can read without converting, None if the pixels have to go through convert_into
+    pub fn native_format(&self) -> Option<PixelFormat> {
+        match self.fast_path {
+            FastPath::Bytes32(r, g, b) => PixelFormat::from_channels(4, (r, g, b)),
+            FastPath::Bytes24(r, g, b) => PixelFormat::from_channels(3, (r, g, b)),
+            FastPath::None => None,
+        }
+    }
+
+    //Reads one pixel value, index is the byte where the pixel starts
+    fn pixel(&self, data: &[u8], index: usize) -> u32 {
+        let bytes = &data[index..index + self.layout.bits_per_pixel as usize / 8];
+        match self.layout.byte_order {
+            ByteOrder::LsbFirst => bytes.iter().rev().fold(0, |a, b| (a << 8) | *b as u32),
+            ByteOrder::MsbFirst => bytes.iter().fold(0, |a, b| (a << 8) | *b as u32),
+        }
+    }
+
+    //Converts a width x height image to RGBA, the output buffer is reused between calls
+    pub fn convert_into(&self, data: &[u8], width: u32, height: u32, output: &mut Vec<u8>) {
+        let stride = self.layout.stride(width);
+        let bytes_per_pixel = self.layout.bits_per_pixel as usize / 8;
+        let row_bytes = width as usize * bytes_per_pixel;
+        output.resize((width * height * 4) as usize, 0);
+
+        for (y, row) in output.chunks_exact_mut(width as usize * 4).enumerate() {
+            let start = y * stride;
+            let source = match data.get(start..start + row_bytes) {
+                Some(a) => a,
+                //Short replies leave the rest of the frame black
+                None => {
+                    row.fill(0);
+                    continue;
+                }
+            };
+
+            match self.fast_path {
+                FastPath::Bytes32(r, g, b) => {
+                    for (out, pixel) in row.chunks_exact_mut(4).zip(source.chunks_exact(4)) {
+                        out[0] = pixel[r];
+                        out[1] = pixel[g];
+                        out[2] = pixel[b];
+                        out[3] = 255;
+                    }
+                }
+                FastPath::Bytes24(r, g, b) => {
+                    for (out, pixel) in row.chunks_exact_mut(4).zip(source.chunks_exact(3)) {
+                        out[0] = pixel[r];
+                        out[1] = pixel[g];
+                        out[2] = pixel[b];
+                        out[3] = 255;
+                    }
+                }
+                FastPath::None => {
+                    for (x, out) in row.chunks_exact_mut(4).enumerate() {
+                        let pixel = self.pixel(source, x * bytes_per_pixel);
+                        out[0] = self.red.get(pixel);
+                        out[1] = self.green.get(pixel);
+                        out[2] = self.blue.get(pixel);
+                        out[3] = 255;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(bits_per_pixel: u8, byte_order: ByteOrder, masks: (u32, u32, u32)) -> PixelLayout {
+        PixelLayout {
+            bits_per_pixel,
+            scanline_pad: 32,
+            byte_order,
+            red_mask: masks.0,
+            green_mask: masks.1,
+            blue_mask: masks.2,
+        }
+    }
+
+    fn convert(layout: PixelLayout, data: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let mut output = Vec::new();
+        PixelConverter::new(layout)
+            .unwrap()
+            .convert_into(data, width, height, &mut output);
+        output
+    }
+
+    const RGB888: (u32, u32, u32) = (0xff0000, 0x00ff00, 0x0000ff);
+
+    #[test]
+    fn depth_24_bgrx_lsb() {
+        let data = [0x30, 0x20, 0x10, 0x00, 0xff, 0x80, 0x00, 0x00];
+        let output = convert(layout(32, ByteOrder::LsbFirst, RGB888), &data, 2, 1);
+        assert_eq!(output, [0x10, 0x20, 0x30, 255, 0x00, 0x80, 0xff, 255]);
+    }
+
+    #[test]
+    fn depth_24_xrgb_msb() {
+        let data = [0x00, 0x10, 0x20, 0x30];
+        let output = convert(layout(32, ByteOrder::MsbFirst, RGB888), &data, 1, 1);
+        assert_eq!(output, [0x10, 0x20, 0x30, 255]);
+    }
+
+    #[test]
+    fn packed_24_bits_with_padding() {
+        //Two pixels are 6 bytes, padded to 8 by the 32 bit scanline pad
+        let data = [
+            0x03, 0x02, 0x01, 0x06, 0x05, 0x04, 0xaa, 0xaa, //
+            0x09, 0x08, 0x07, 0x0c, 0x0b, 0x0a, 0xaa, 0xaa,
+        ];
+        let output = convert(layout(24, ByteOrder::LsbFirst, RGB888), &data, 2, 2);
+        assert_eq!(
+            output,
+            [1, 2, 3, 255, 4, 5, 6, 255, 7, 8, 9, 255, 10, 11, 12, 255]
+        );
+    }
+
+    #[test]
+    fn empty_mask_is_black() {
+        //No red, 8 bit green and blue
+        let data = [0x30, 0x20, 0x10, 0x00, 0xff, 0x80, 0x7f, 0x00];
+        let output = convert(layout(32, ByteOrder::LsbFirst, (0, 0x00ff00, 0x0000ff)), &data, 2, 1);
+        assert_eq!(output, [0, 0x20, 0x30, 255, 0, 0x80, 0xff, 255]);
+
+        //5-6-5 without blue
+        let pixels: Vec<u8> = [0xf81fu16, 0x07e0].iter().flat_map(|a| a.to_le_bytes()).collect();
+        let output = convert(layout(16, ByteOrder::LsbFirst, (0xf800, 0x07e0, 0)), &pixels, 2, 1);
+        assert_eq!(output, [255, 0, 0, 255, 0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn depth_16_rgb565_both_orders() {
+        let masks = (0xf800, 0x07e0, 0x001f);
+        //Pure red, pure green, pure blue and a mid gray (16, 32, 16)
+        let pixels: [u16; 4] = [0xf800, 0x07e0, 0x001f, 0x8410];
+        let expected = [
+            255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 132, 130, 132, 255,
+        ];
+
+        let lsb: Vec<u8> = pixels.iter().flat_map(|a| a.to_le_bytes()).collect();
+        assert_eq!(convert(layout(16, ByteOrder::LsbFirst, masks), &lsb, 4, 1), expected);
+
+        let msb: Vec<u8> = pixels.iter().flat_map(|a| a.to_be_bytes()).collect();
+        assert_eq!(convert(layout(16, ByteOrder::MsbFirst, masks), &msb, 4, 1), expected);
+    }
+
+    #[test]
+    fn depth_30_deep_color() {
+        let masks = (0x3ff0_0000, 0x000f_fc00, 0x0000_03ff);
+        //Full red, half green (512) and no blue
+        let pixel: u32 = (0x3ff << 20) | (512 << 10);
+        let expected = [255, 128, 0, 255];
+
+        assert_eq!(
+            convert(layout(32, ByteOrder::LsbFirst, masks), &pixel.to_le_bytes(), 1, 1),
+            expected
+        );
+        assert_eq!(
+            convert(layout(32, ByteOrder::MsbFirst, masks), &pixel.to_be_bytes(), 1, 1),
+            expected
+        );
+    }
+
+    #[test]
+    fn depth_8_without_masks_is_332() {
+        //Red, green, blue, black and 3-4-1 as RRRGGGBB
+        let data = [0xe0, 0x1c, 0x03, 0x00, 0x71];
+        let output = convert(layout(8, ByteOrder::LsbFirst, (0, 0, 0)), &data, 5, 1);
+        assert_eq!(
+            output,
+            [
+                255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 0, 0, 0, 255, 109, 146, 85, 255
+            ]
+        );
+    }
+
+    #[test]
+    fn native_formats() {
+        let native = |layout| PixelConverter::new(layout).unwrap().native_format();
+        assert_eq!(native(layout(32, ByteOrder::LsbFirst, RGB888)), Some(PixelFormat::Bgrx));
+        assert_eq!(native(layout(32, ByteOrder::MsbFirst, RGB888)), Some(PixelFormat::Xrgb));
+        assert_eq!(native(layout(24, ByteOrder::LsbFirst, RGB888)), Some(PixelFormat::Bgr));
+        assert_eq!(native(layout(16, ByteOrder::LsbFirst, (0xf800, 0x07e0, 0x001f))), None);
+    }
+
+    #[test]
+    fn short_data_leaves_black_rows() {
+        let data = [0x30, 0x20, 0x10, 0x00];
+        let output = convert(layout(32, ByteOrder::LsbFirst, RGB888), &data, 1, 2);
+        assert_eq!(output, [0x10, 0x20, 0x30, 255, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn output_buffer_is_reused() {
+        let converter = PixelConverter::new(layout(32, ByteOrder::LsbFirst, RGB888)).unwrap();
+        let mut output = vec![7; 64];
+        converter.convert_into(&[1, 2, 3, 0], 1, 1, &mut output);
+        assert_eq!(output, [3, 2, 1, 255]);
+    }
+
+    #[test]
+    fn unsupported_bits_per_pixel() {
+        assert!(PixelConverter::new(layout(4, ByteOrder::LsbFirst, RGB888)).is_err());
+    }
+}
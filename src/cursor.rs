@@ -0,0 +1,88 @@
+use xcb::xfixes;
+
+use crate::capture::Rect;
+use crate::frame::PixelFormat;
+
+//Copy of the pointer image as XFixes reports it
+pub struct CursorImage {
+    //Top left corner of the image in root coordinates, the hotspot is already applied
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+    pub serial: u32,
+    //Premultiplied ARGB, one u32 per pixel
+    pub pixels: Vec<u32>,
+}
+
+impl CursorImage {
+    pub fn bounds(&self) -> Rect {
+        Rect {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+//XFixes needs to know the client version before GetCursorImage can be used
+pub fn init_extension(conn: &xcb::Connection) -> xcb::Result<()> {
+    conn.wait_for_reply(conn.send_request(&xfixes::QueryVersion {
+        client_major_version: 5,
+        client_minor_version: 0,
+    }))?;
+    Ok(())
+}
+
+pub fn cursor_image(conn: &xcb::Connection) -> xcb::Result<CursorImage> {
+    let reply = conn.wait_for_reply(conn.send_request(&xfixes::GetCursorImage {}))?;
+    Ok(CursorImage {
+        x: reply.x().saturating_sub(reply.xhot() as i16),
+        y: reply.y().saturating_sub(reply.yhot() as i16),
+        width: reply.width(),
+        height: reply.height(),
+        serial: reply.cursor_serial(),
+        pixels: reply.cursor_image().to_vec(),
+    })
+}
+
+//Draws the cursor over a frame whose top left corner is at origin in root coordinates
+pub fn blend_cursor(
+    frame: &mut [u8],
+    frame_width: u32,
+    frame_height: u32,
+    pitch: usize,
+    format: PixelFormat,
+    origin: (i32, i32),
+    cursor: &CursorImage,
+) {
+    let left = cursor.x as i32 - origin.0;
+    let top = cursor.y as i32 - origin.1;
+    let (red, green, blue) = format.channels();
+    for cy in 0..cursor.height as i32 {
+        let y = top + cy;
+        if y < 0 || y >= frame_height as i32 {
+            continue;
+        }
+        for cx in 0..cursor.width as i32 {
+            let x = left + cx;
+            if x < 0 || x >= frame_width as i32 {
+                continue;
+            }
+            let argb = cursor.pixels[(cy * cursor.width as i32 + cx) as usize];
+            let alpha = argb >> 24;
+            if alpha == 0 {
+                continue;
+            }
+            let inverse = 255 - alpha;
+            let index = y as usize * pitch + x as usize * format.bytes_per_pixel();
+            //The source is premultiplied, so only the destination is scaled
+            for (channel, shift) in [(red, 16), (green, 8), (blue, 0)] {
+                let source = (argb >> shift) & 0xff;
+                let destination = frame[index + channel] as u32;
+                frame[index + channel] = (source + (destination * inverse + 127) / 255).min(255) as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use xcb::{damage, x, xfixes, Xid};
+
+use crate::capture::Rect;
+
+//Asks the server for the versions the client was written against, both extensions require it
+pub fn init_extensions(conn: &xcb::Connection) -> xcb::Result<()> {
+    let damage_cookie = conn.send_request(&damage::QueryVersion {
+        client_major_version: 1,
+        client_minor_version: 1,
+    });
+    let xfixes_cookie = conn.send_request(&xfixes::QueryVersion {
+        client_major_version: 5,
+        client_minor_version: 0,
+    });
+    conn.wait_for_reply(damage_cookie)?;
+    conn.wait_for_reply(xfixes_cookie)?;
+    Ok(())
+}
+
+//Tracks which parts of a drawable changed since the last poll
+pub struct DamageTracker {
+    pub drawable: x::Drawable,
+    damage: damage::Damage,
+    //Server side region where the accumulated damage is moved on every poll
+    region: xfixes::Region,
+    dirty: Vec<Rect>,
+}
+
+impl DamageTracker {
+    pub fn new(conn: &xcb::Connection, drawable: x::Drawable) -> xcb::Result<Self> {
+        let damage: damage::Damage = conn.generate_id();
+        let region: xfixes::Region = conn.generate_id();
+        //NonEmpty sends a single event until the damage is subtracted, so the event queue stays small
+        conn.send_and_check_request(&damage::Create {
+            damage,
+            drawable,
+            level: damage::ReportLevel::NonEmpty,
+        })?;
+        conn.send_and_check_request(&xfixes::CreateRegion {
+            region,
+            rectangles: &[],
+        })?;
+        Ok(DamageTracker {
+            drawable,
+            damage,
+            region,
+            dirty: Vec::new(),
+        })
+    }
+
+    //Drains the pending events and returns true if the drawable changed since the last poll
+    pub fn poll(&mut self, conn: &xcb::Connection) -> xcb::Result<bool> {
+        let mut damaged = false;
+        while let Some(event) = conn.poll_for_event()? {
+            if let xcb::Event::Damage(damage::Event::Notify(a)) = event {
+                if a.damage() == self.damage {
+                    damaged = true;
+                }
+            }
+        }
+
+        self.dirty.clear();
+        if !damaged {
+            return Ok(false);
+        }
+
+        conn.send_and_check_request(&damage::Subtract {
+            damage: self.damage,
+            repair: xfixes::Region::none(),
+            parts: self.region,
+        })?;
+        let reply = conn.wait_for_reply(conn.send_request(&xfixes::FetchRegion { region: self.region }))?;
+        self.dirty.extend(reply.rectangles().iter().map(|a| Rect {
+            x: a.x,
+            y: a.y,
+            width: a.width,
+            height: a.height,
+        }));
+        Ok(!self.dirty.is_empty())
+    }
+
+    //Rectangles that changed before the last poll, in drawable coordinates
+    pub fn dirty_rects(&self) -> &[Rect] {
+        &self.dirty
+    }
+
+    pub fn destroy(self, conn: &xcb::Connection) {
+        conn.send_request(&damage::Destroy { damage: self.damage });
+        conn.send_request(&xfixes::DestroyRegion { region: self.region });
+    }
+}
@@ -0,0 +1,249 @@
+use std::str::FromStr;
+
+use image::ImageEncoder;
+
+use crate::adaptive::AdaptiveOptions;
+use crate::frame::Frame;
+use crate::h264::{H264Encoder, H264Options};
+use crate::scale::ScaleOptions;
+use crate::transform::Transform;
+use crate::jpeg::{JpegEncoder, JpegOptions, Subsampling};
+use crate::raw::{Dither, RawEncoder};
+
+//Image formats the frames can be sent in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Codec {
+    Jpeg,
+    //The lossless codecs ignore the quality
+    Png,
+    Webp,
+    Qoi,
+    //Video, frames depend on the ones before them
+    H264,
+    //JPEG with only the luminance
+    JpegGray,
+    //Uncompressed, see RawEncoder for their layout
+    Gray4,
+    Mono,
+    Rgb565,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 9] = [
+        Codec::Jpeg,
+        Codec::Png,
+        Codec::Webp,
+        Codec::Qoi,
+        Codec::H264,
+        Codec::JpegGray,
+        Codec::Gray4,
+        Codec::Mono,
+        Codec::Rgb565,
+    ];
+
+    //Number that identifies the codec on the wire
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::Jpeg => 1,
+            Codec::Png => 2,
+            Codec::Webp => 3,
+            Codec::Qoi => 4,
+            Codec::H264 => 5,
+            Codec::JpegGray => 6,
+            Codec::Gray4 => 7,
+            Codec::Mono => 8,
+            Codec::Rgb565 => 9,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Codec::ALL.into_iter().find(|a| a.id() == id)
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(Codec::Jpeg),
+            "png" => Ok(Codec::Png),
+            "webp" => Ok(Codec::Webp),
+            "qoi" => Ok(Codec::Qoi),
+            "h264" | "h.264" => Ok(Codec::H264),
+            "jpeg-gray" | "gray" => Ok(Codec::JpegGray),
+            "gray4" => Ok(Codec::Gray4),
+            "mono" | "gray1" => Ok(Codec::Mono),
+            "rgb565" => Ok(Codec::Rgb565),
+            _ => Err(format!(
+                "Not a valid codec {s}, use jpeg, png, webp, qoi, h264, jpeg-gray, gray4, mono or rgb565"
+            )),
+        }
+    }
+}
+
+//Everything that changes how the frames are encoded
+#[derive(Clone, Debug)]
+pub struct EncoderOptions {
+    pub codec: Codec,
+    pub quality: u8,
+    pub jpeg: JpegOptions,
+    pub h264: H264Options,
+    //Only for the gray4 and mono codecs
+    pub dither: Dither,
+    //Tile size when only the changed tiles are sent, None sends whole frames
+    pub tiles: Option<u16>,
+    //Rotation and mirroring, applied before the frames are resized
+    pub transform: Transform,
+    //Size the frames are resized to before they are encoded
+    pub output: ScaleOptions,
+    pub adaptive: AdaptiveOptions,
+    //Largest frame in bytes the receiver takes, the quality and scale are searched to fit it
+    pub max_frame_size: Option<usize>,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        EncoderOptions {
+            codec: Codec::Jpeg,
+            quality: 25,
+            jpeg: JpegOptions::default(),
+            h264: H264Options::default(),
+            dither: Dither::FloydSteinberg,
+            tiles: None,
+            transform: Transform::default(),
+            output: ScaleOptions::default(),
+            adaptive: AdaptiveOptions::default(),
+            max_frame_size: None,
+        }
+    }
+}
+
+//Turns frames into the bytes that are sent to the receiver
+pub trait Encoder {
+    fn codec(&self) -> Codec;
+    //Encodes the frame into a pooled buffer, give it back with recycle once it was sent
+    fn encode(&mut self, frame: &Frame) -> Result<Vec<u8>, String>;
+    fn recycle(&mut self, buffer: Vec<u8>);
+    //The next frame has to be decodable on its own, only video codecs have frames that are not
+    fn request_keyframe(&mut self) {}
+    //The last encoded frame can be decoded without the ones before it
+    fn keyframe(&self) -> bool {
+        true
+    }
+    //Changes the quality of the next frames, the codecs without a quality ignore it
+    fn set_quality(&mut self, _quality: u8) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+//Creates the encoder for the codec of the options
+pub fn new_encoder(options: &EncoderOptions) -> Result<Box<dyn Encoder>, String> {
+    match options.codec {
+        Codec::Jpeg => Ok(Box::new(JpegEncoder::new(options.quality, options.jpeg)?)),
+        Codec::JpegGray => {
+            let jpeg = JpegOptions {
+                subsampling: Subsampling::Gray,
+                ..options.jpeg
+            };
+            Ok(Box::new(JpegEncoder::new(options.quality, jpeg)?))
+        }
+        Codec::H264 => Ok(Box::new(H264Encoder::new(options.h264)?)),
+        codec @ (Codec::Gray4 | Codec::Mono | Codec::Rgb565) => Ok(Box::new(RawEncoder::new(codec, options.dither))),
+        codec => Ok(Box::new(LosslessEncoder::new(codec))),
+    }
+}
+
+//Most frames that are expected to be in flight at the same time
+const POOL_SIZE: usize = 4;
+
+//Keeps the buffers of the sent frames, so the encoder does not allocate once they are warm
+#[derive(Default)]
+pub struct BufferPool {
+    free: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    //Buffer of exactly len bytes, the old content is left there
+    pub fn take(&mut self, len: usize) -> Vec<u8> {
+        let mut buffer = self.free.pop().unwrap_or_default();
+        buffer.resize(len, 0);
+        buffer
+    }
+
+    pub fn give_back(&mut self, buffer: Vec<u8>) {
+        if self.free.len() < POOL_SIZE {
+            self.free.push(buffer);
+        }
+    }
+}
+
+//Copies the frame to tightly packed RGB, the layout the image crate encoders take
+pub fn pack_rgb(frame: &Frame, output: &mut Vec<u8>) {
+    let (red, green, blue) = frame.format.channels();
+    let bytes_per_pixel = frame.format.bytes_per_pixel();
+    let row_bytes = frame.width as usize * bytes_per_pixel;
+    output.clear();
+    for y in 0..frame.height as usize {
+        let start = y * frame.pitch;
+        let row = &frame.data[start..start + row_bytes];
+        for pixel in row.chunks_exact(bytes_per_pixel) {
+            output.extend_from_slice(&[pixel[red], pixel[green], pixel[blue]]);
+        }
+    }
+}
+
+//PNG, WebP and QOI through the image crate, all of them lossless
+pub struct LosslessEncoder {
+    codec: Codec,
+    //Packed RGB copy of the frame, reused between frames
+    rgb: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl LosslessEncoder {
+    pub fn new(codec: Codec) -> Self {
+        LosslessEncoder {
+            codec,
+            rgb: Vec::new(),
+            pool: BufferPool::default(),
+        }
+    }
+}
+
+impl Encoder for LosslessEncoder {
+    fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    fn encode(&mut self, frame: &Frame) -> Result<Vec<u8>, String> {
+        use image::codecs::{png, qoi, webp};
+
+        pack_rgb(frame, &mut self.rgb);
+        let mut buffer = self.pool.take(0);
+        let (width, height, color) = (frame.width, frame.height, image::ExtendedColorType::Rgb8);
+        let result = match self.codec {
+            //Fast compression, the frames have to be ready in a few milliseconds
+            Codec::Png => png::PngEncoder::new_with_quality(
+                &mut buffer,
+                png::CompressionType::Fast,
+                png::FilterType::Adaptive,
+            )
+            .write_image(&self.rgb, width, height, color),
+            Codec::Webp => webp::WebPEncoder::new_lossless(&mut buffer).write_image(&self.rgb, width, height, color),
+            Codec::Qoi => qoi::QoiEncoder::new(&mut buffer).write_image(&self.rgb, width, height, color),
+            codec => return Err(format!("{codec:?} is not a lossless codec")),
+        };
+        match result {
+            Ok(_) => Ok(buffer),
+            Err(a) => {
+                self.pool.give_back(buffer);
+                Err(a.to_string())
+            }
+        }
+    }
+
+    fn recycle(&mut self, buffer: Vec<u8>) {
+        self.pool.give_back(buffer);
+    }
+}
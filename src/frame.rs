@@ -0,0 +1,57 @@
+//Captured pixels on their way to the encoder
+
+//Memory layout of a pixel, named by the order of the bytes from the lowest address
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    Rgba,
+    Rgbx,
+    Bgrx,
+    Xrgb,
+    Xbgr,
+    Rgb,
+    Bgr,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb | PixelFormat::Bgr => 3,
+            _ => 4,
+        }
+    }
+
+    //Byte offsets of red, green and blue inside a pixel
+    pub fn channels(self) -> (usize, usize, usize) {
+        match self {
+            PixelFormat::Rgba | PixelFormat::Rgbx | PixelFormat::Rgb => (0, 1, 2),
+            PixelFormat::Bgrx | PixelFormat::Bgr => (2, 1, 0),
+            PixelFormat::Xrgb => (1, 2, 3),
+            PixelFormat::Xbgr => (3, 2, 1),
+        }
+    }
+
+    //Format with these channel offsets, None if no format matches
+    pub fn from_channels(bytes_per_pixel: usize, channels: (usize, usize, usize)) -> Option<Self> {
+        [
+            PixelFormat::Rgbx,
+            PixelFormat::Bgrx,
+            PixelFormat::Xrgb,
+            PixelFormat::Xbgr,
+            PixelFormat::Rgb,
+            PixelFormat::Bgr,
+        ]
+        .into_iter()
+        .find(|a| a.bytes_per_pixel() == bytes_per_pixel && a.channels() == channels)
+    }
+}
+
+//A frame borrowed from whoever captured it, rows can be padded so pitch is given apart
+#[derive(Clone, Copy)]
+pub struct Frame<'a> {
+    pub data: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+    //Bytes between the start of two rows
+    pub pitch: usize,
+    pub format: PixelFormat,
+}
@@ -0,0 +1,129 @@
+use openh264::encoder::{
+    BitRate, EncoderConfig, FrameRate, FrameType, IntraFramePeriod, RateControlMode, UsageType,
+};
+use openh264::formats::{RgbSliceU8, YUVBuffer};
+use openh264::OpenH264API;
+
+use crate::encoder::{pack_rgb, BufferPool, Codec, Encoder};
+use crate::frame::Frame;
+
+//Frame rate the rate control is tuned for, the sources deliver at most this many frames
+const FRAME_RATE: f32 = 30.0;
+
+//Settings of the H.264 stream, the quality is not used, the bitrate decides it
+#[derive(Clone, Copy, Debug)]
+pub struct H264Options {
+    //Frames between keyframes, 0 sends them only when a receiver joins
+    pub keyframe_interval: u32,
+    //Bits per second the encoder aims for
+    pub bitrate: u32,
+}
+
+impl Default for H264Options {
+    fn default() -> Self {
+        H264Options {
+            keyframe_interval: 120,
+            bitrate: 2_000_000,
+        }
+    }
+}
+
+//Software H.264 encoder with openh264, every frame is an Annex B access unit
+//and keyframes carry the SPS and PPS so a receiver can start decoding from them
+pub struct H264Encoder {
+    encoder: openh264::encoder::Encoder,
+    //Packed RGB and YUV copies of the frame, reused between frames
+    rgb: Vec<u8>,
+    yuv: Option<YUVBuffer>,
+    //Size of the YUV buffer, always even
+    width: usize,
+    height: usize,
+    //Nothing was encoded yet, the first frame is always a keyframe
+    started: bool,
+    force_keyframe: bool,
+    //The last frame was an IDR or I frame
+    last_keyframe: bool,
+    pool: BufferPool,
+}
+
+impl H264Encoder {
+    pub fn new(options: H264Options) -> Result<Self, String> {
+        let period = match options.keyframe_interval {
+            0 => IntraFramePeriod::auto(),
+            a => IntraFramePeriod::from_num_frames(a),
+        };
+        let config = EncoderConfig::new()
+            .usage_type(UsageType::ScreenContentRealTime)
+            .rate_control_mode(RateControlMode::Bitrate)
+            .bitrate(BitRate::from_bps(options.bitrate))
+            .max_frame_rate(FrameRate::from_hz(FRAME_RATE))
+            .intra_frame_period(period)
+            //Skipped frames would reach the receiver as empty frames
+            .skip_frames(false);
+        let encoder =
+            openh264::encoder::Encoder::with_api_config(OpenH264API::from_source(), config).map_err(|a| a.to_string())?;
+        Ok(H264Encoder {
+            encoder,
+            rgb: Vec::new(),
+            yuv: None,
+            width: 0,
+            height: 0,
+            started: false,
+            force_keyframe: false,
+            last_keyframe: false,
+            pool: BufferPool::default(),
+        })
+    }
+}
+
+impl Encoder for H264Encoder {
+    fn codec(&self) -> Codec {
+        Codec::H264
+    }
+
+    fn encode(&mut self, frame: &Frame) -> Result<Vec<u8>, String> {
+        //4:2:0 needs even sizes, an odd last row or column is dropped
+        let width = frame.width as usize & !1;
+        let height = frame.height as usize & !1;
+        if width == 0 || height == 0 {
+            return Err(format!("The frame is too small for H.264: {}x{}", frame.width, frame.height));
+        }
+        let even = Frame {
+            width: width as u32,
+            height: height as u32,
+            ..*frame
+        };
+        pack_rgb(&even, &mut self.rgb);
+        if self.yuv.is_none() || self.width != width || self.height != height {
+            //openh264 starts again with a keyframe when the size changes
+            self.yuv = Some(YUVBuffer::new(width, height));
+            self.width = width;
+            self.height = height;
+        }
+        let yuv = self.yuv.as_mut().unwrap();
+        yuv.read_rgb8(RgbSliceU8::new(&self.rgb, (width, height)));
+
+        if self.force_keyframe && self.started {
+            self.encoder.force_intra_frame();
+        }
+        self.force_keyframe = false;
+        let stream = self.encoder.encode(&*yuv).map_err(|a| a.to_string())?;
+        self.started = true;
+        self.last_keyframe = matches!(stream.frame_type(), FrameType::IDR | FrameType::I);
+        let mut buffer = self.pool.take(0);
+        stream.write_vec(&mut buffer);
+        Ok(buffer)
+    }
+
+    fn recycle(&mut self, buffer: Vec<u8>) {
+        self.pool.give_back(buffer);
+    }
+
+    fn request_keyframe(&mut self) {
+        self.force_keyframe = true;
+    }
+
+    fn keyframe(&self) -> bool {
+        self.last_keyframe
+    }
+}
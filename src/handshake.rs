@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use crate::encoder::EncoderOptions;
+use crate::message::Hello;
+use crate::protocol::VERSION;
+
+//How long a new receiver has to send its hello, older receivers never send one
+pub const HELLO_TIMEOUT: Duration = Duration::from_millis(500);
+
+//Protocol version and encoder options for a receiver, the ones of the server adjusted to what it can do
+pub fn negotiate(hello: &Hello, defaults: &EncoderOptions) -> Result<(u8, EncoderOptions), String> {
+    if hello.version == 0 {
+        return Err("The receiver sent protocol version 0, it does not exist".to_string());
+    }
+    //Falling back to the codec of the server would send frames the receiver said it can not decode
+    if hello.codecs.is_empty() && !hello.unknown_codecs.is_empty() {
+        return Err(format!(
+            "The receiver only decodes the codecs {:?}, this server has none of them",
+            hello.unknown_codecs
+        ));
+    }
+    let mut options = defaults.clone();
+    //The codec of the server if the receiver has it, else the one the receiver prefers
+    if !hello.codecs.is_empty() && !hello.codecs.contains(&defaults.codec) {
+        options.codec = hello.codecs[0];
+    }
+    //A size given to the server wins over the screen of the receiver
+    if options.output.size.is_none() {
+        options.output.size = hello.screen;
+    }
+    options.max_frame_size = match (defaults.max_frame_size, hello.max_frame_size) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    Ok((hello.version.min(VERSION), options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Codec;
+    use crate::message::{ClientMessage, MessageReader};
+    use crate::scale::Size;
+
+    fn hello(codecs: Vec<Codec>) -> Hello {
+        Hello {
+            version: 1,
+            screen: Some(Size {
+                width: 1280,
+                height: 720,
+            }),
+            codecs,
+            unknown_codecs: Vec::new(),
+            max_frame_size: Some(50_000),
+        }
+    }
+
+    #[test]
+    fn keeps_the_server_codec_if_supported() {
+        let (_, options) = negotiate(&hello(vec![Codec::Png, Codec::Jpeg]), &EncoderOptions::default()).unwrap();
+        assert_eq!(options.codec, Codec::Jpeg);
+    }
+
+    #[test]
+    fn picks_the_receiver_codec_otherwise() {
+        let (_, options) = negotiate(&hello(vec![Codec::Mono, Codec::Gray4]), &EncoderOptions::default()).unwrap();
+        assert_eq!(options.codec, Codec::Mono);
+    }
+
+    #[test]
+    fn rejects_only_unknown_codecs() {
+        let unknown = Hello {
+            unknown_codecs: vec![200, 201],
+            ..hello(vec![])
+        };
+        assert!(negotiate(&unknown, &EncoderOptions::default()).is_err());
+        //Some of them are known, the known ones are used
+        let some = Hello {
+            unknown_codecs: vec![200],
+            ..hello(vec![Codec::Png])
+        };
+        assert_eq!(negotiate(&some, &EncoderOptions::default()).unwrap().1.codec, Codec::Png);
+    }
+
+    #[test]
+    fn sizes() {
+        let (_, options) = negotiate(&hello(vec![]), &EncoderOptions::default()).unwrap();
+        assert_eq!(options.output.size, hello(vec![]).screen);
+        assert_eq!(options.max_frame_size, Some(50_000));
+
+        let mut defaults = EncoderOptions::default();
+        defaults.output.size = Some(Size {
+            width: 640,
+            height: 480,
+        });
+        defaults.max_frame_size = Some(20_000);
+        let (_, options) = negotiate(&hello(vec![]), &defaults).unwrap();
+        assert_eq!(options.output.size, defaults.output.size);
+        assert_eq!(options.max_frame_size, Some(20_000));
+    }
+
+    #[test]
+    fn versions() {
+        let newer = Hello {
+            version: VERSION + 1,
+            ..hello(vec![])
+        };
+        assert_eq!(negotiate(&newer, &EncoderOptions::default()).unwrap().0, VERSION);
+        let zero = Hello {
+            version: 0,
+            ..hello(vec![])
+        };
+        assert!(negotiate(&zero, &EncoderOptions::default()).is_err());
+    }
+
+    #[test]
+    fn parses_the_hello() {
+        //Codec 200 does not exist and is left out
+        let bytes = [0, 6, 0, 0, 0, 13, 1, 0x05, 0x00, 0x02, 0xd0, 0, 0, 0x10, 0, 3, 8, 200, 1];
+        let mut reader = MessageReader::default();
+        reader.push(&bytes);
+        let expected = Hello {
+            version: 1,
+            screen: Some(Size {
+                width: 1280,
+                height: 720,
+            }),
+            codecs: vec![Codec::Mono, Codec::Jpeg],
+            unknown_codecs: vec![200],
+            max_frame_size: Some(4096),
+        };
+        assert_eq!(reader.next_message(), Some(Ok(ClientMessage::Hello(expected))));
+        assert_eq!(reader.next_message(), None);
+    }
+}
@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+
+use crate::error::{GabinatorError, Logger, LoggerLevel};
+
+//Most wheel steps injected for one message, so a broken receiver can not scroll forever
+const MAX_SCROLL_STEPS: i32 = 10;
+
+#[cfg(target_os = "linux")]
+const XK_RETURN: u32 = 0xff0d;
+#[cfg(target_os = "linux")]
+const XK_TAB: u32 = 0xff09;
+#[cfg(target_os = "linux")]
+const XK_BACKSPACE: u32 = 0xff08;
+#[cfg(target_os = "linux")]
+const XK_SHIFT_L: u32 = 0xffe1;
+
+//Keysym that types a character: Latin-1 has its own keysyms, the rest of Unicode is 0x01000000 + the code point
+#[cfg(target_os = "linux")]
+fn char_keysym(character: char) -> Option<u32> {
+    match character {
+        '\n' | '\r' => Some(XK_RETURN),
+        '\t' => Some(XK_TAB),
+        '\u{8}' => Some(XK_BACKSPACE),
+        a if a.is_control() => None,
+        a if (a as u32) < 0x100 => Some(a as u32),
+        a => Some(0x0100_0000 | a as u32),
+    }
+}
+
+//Core keyboard mapping of the X server, keysyms_per_keycode keysyms for every keycode from min_keycode on
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+struct Keymap {
+    min_keycode: u8,
+    keysyms_per_keycode: usize,
+    keysyms: Vec<u32>,
+}
+
+#[cfg(target_os = "linux")]
+impl Keymap {
+    //Keycode that types keysym in the first group and whether it needs shift
+    fn find(&self, keysym: u32) -> Option<(u8, bool)> {
+        for column in 0..self.keysyms_per_keycode.min(2) {
+            let found = self
+                .keysyms
+                .chunks_exact(self.keysyms_per_keycode)
+                .position(|a| a[column] == keysym);
+            if let Some(index) = found {
+                return Some((self.min_keycode + index as u8, column == 1));
+            }
+        }
+        None
+    }
+
+    //Keycodes without any keysym, they can be remapped to the characters the layout does not have
+    fn unused(&self) -> Vec<u8> {
+        self.keysyms
+            .chunks_exact(self.keysyms_per_keycode)
+            .enumerate()
+            .filter(|(_, a)| a.iter().all(|b| *b == 0))
+            .map(|(index, _)| self.min_keycode + index as u8)
+            .collect()
+    }
+
+    //Hides a keycode from find, the spare keycodes can hold another keysym than the server says
+    fn forget(&mut self, keycode: u8) {
+        let start = (keycode - self.min_keycode) as usize * self.keysyms_per_keycode;
+        self.keysyms[start..start + self.keysyms_per_keycode].fill(0);
+    }
+}
+
+//Pointer input already mapped to the desktop, positions are root coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DesktopPointer {
+    MoveTo(i32, i32),
+    MoveBy(i32, i32),
+    Press(u8),
+    Release(u8),
+    Scroll(i32, i32),
+}
+
+//Injects the input of the receiver with XTest, the X server handles it like a real mouse and keyboard
+#[cfg(target_os = "linux")]
+pub struct InputInjector {
+    conn: xcb::Connection,
+    root: xcb::x::Window,
+    keymap: Keymap,
+    //Keycodes that were free when the injector started and the keysym each one is remapped to now
+    spare: Vec<(u8, Option<u32>)>,
+    //Spare keycode to remap next, they are reused in turns
+    next_spare: usize,
+    //Keys the receiver holds down, their keycodes must not be remapped until they are released
+    pressed: Vec<u8>,
+    //Pointer buttons the receiver holds down
+    buttons: Vec<u8>,
+}
+
+//Core event types that XTest can fake
+#[cfg(target_os = "linux")]
+const KEY_PRESS: u8 = 2;
+#[cfg(target_os = "linux")]
+const KEY_RELEASE: u8 = 3;
+#[cfg(target_os = "linux")]
+const BUTTON_PRESS: u8 = 4;
+#[cfg(target_os = "linux")]
+const BUTTON_RELEASE: u8 = 5;
+#[cfg(target_os = "linux")]
+const MOTION_NOTIFY: u8 = 6;
+
+#[cfg(target_os = "linux")]
+impl InputInjector {
+    pub fn new(config: &HashMap<String, String>) -> Result<Self, GabinatorError> {
+        let (conn, screen_number) = match xcb::Connection::connect_with_extensions(None, &[xcb::Extension::Test], &[]) {
+            Ok(a) => a,
+            Err(a) => {
+                return Err(GabinatorError::newCapture(
+                    format!("Not able to connect to the X server for the input: {a}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                ))
+            }
+        };
+        let version = conn.wait_for_reply(conn.send_request(&xcb::xtest::GetVersion {
+            major_version: 2,
+            minor_version: 2,
+        }));
+        if version.is_err() {
+            return Err(GabinatorError::newCapture(
+                "XTest is not available, the receiver can not control the pointer",
+                LoggerLevel::Warning,
+                Some(config.clone()),
+            ));
+        }
+        let root = match conn.get_setup().roots().nth(screen_number as usize) {
+            Some(a) => a.root(),
+            None => {
+                return Err(GabinatorError::newCapture(
+                    "The X server has no screen for the input",
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                ))
+            }
+        };
+        let keymap = match load_keymap(&conn) {
+            Ok(a) => a,
+            Err(a) => {
+                return Err(GabinatorError::newCapture(
+                    format!("Not able to read the keyboard mapping: {a}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                ))
+            }
+        };
+        let spare: Vec<(u8, Option<u32>)> = keymap.unused().into_iter().map(|a| (a, None)).collect();
+        if spare.is_empty() {
+            Logger::log(
+                "There are no free keycodes, only the characters of the keyboard layout can be typed".to_string(),
+                LoggerLevel::Warning,
+                Some(config.clone()),
+            );
+        }
+        Ok(InputInjector {
+            conn,
+            root,
+            keymap,
+            spare,
+            next_spare: 0,
+            pressed: Vec::new(),
+            buttons: Vec::new(),
+        })
+    }
+
+    //Presses or releases the key of a keysym, keysyms the layout does not have go to a spare keycode
+    pub fn key(&mut self, keysym: u32, pressed: bool) -> Result<(), String> {
+        self.poll_mapping();
+        let keycode = match self.keymap.find(keysym) {
+            Some((a, false)) => a,
+            //Remapped so the keysym comes out without the receiver holding shift
+            _ => self.remap(keysym, &[])?,
+        };
+        let kind = if pressed { KEY_PRESS } else { KEY_RELEASE };
+        self.pressed.retain(|a| *a != keycode);
+        if pressed {
+            self.pressed.push(keycode);
+        }
+        self.fake(kind, keycode, 0, 0)
+            .map_err(|a| format!("Not able to inject keysym 0x{keysym:x}: {a}"))
+    }
+
+    //Types committed text, shift is pressed for the characters that need it
+    pub fn type_text(&mut self, text: &str) -> Result<(), String> {
+        self.poll_mapping();
+        let shift = self.keymap.find(XK_SHIFT_L).map(|a| a.0);
+        //The clients read a new mapping when its MappingNotify arrives, so a spare keycode remapped for this text
+        //can not be remapped again before the characters typed with it were handled
+        let mut remapped = Vec::new();
+        for character in text.chars() {
+            let keysym = match char_keysym(character) {
+                Some(a) => a,
+                None => return Err(format!("Not able to type the character {character:?}")),
+            };
+            let (keycode, shift) = match (self.keymap.find(keysym), shift) {
+                (Some((a, false)), _) => (a, None),
+                (Some((a, true)), Some(b)) => (a, Some(b)),
+                _ => {
+                    let keycode = self.remap(keysym, &remapped)?;
+                    remapped.push(keycode);
+                    (keycode, None)
+                }
+            };
+            self.tap(keycode, shift)
+                .map_err(|a| format!("Not able to type the character {character:?}: {a}"))?;
+        }
+        Ok(())
+    }
+
+    //Presses and releases a key, with shift held around it if there is one
+    fn tap(&self, keycode: u8, shift: Option<u8>) -> xcb::ProtocolResult<()> {
+        if let Some(a) = shift {
+            self.fake(KEY_PRESS, a, 0, 0)?;
+        }
+        self.fake(KEY_PRESS, keycode, 0, 0)?;
+        self.fake(KEY_RELEASE, keycode, 0, 0)?;
+        if let Some(a) = shift {
+            self.fake(KEY_RELEASE, a, 0, 0)?;
+        }
+        Ok(())
+    }
+
+    //Spare keycode that types keysym, it is remapped if no spare one has it yet. The keycodes in busy are not remapped
+    fn remap(&mut self, keysym: u32, busy: &[u8]) -> Result<u8, String> {
+        if let Some(a) = self.spare.iter().find(|a| a.1 == Some(keysym)) {
+            return Ok(a.0);
+        }
+        let count = self.spare.len();
+        let index = match (0..count)
+            .map(|a| (self.next_spare + a) % count)
+            .find(|a| !self.pressed.contains(&self.spare[*a].0) && !busy.contains(&self.spare[*a].0))
+        {
+            Some(a) => a,
+            None => {
+                return Err(format!(
+                    "There is no free keycode to type keysym 0x{keysym:x}, the {count} spare ones are held or already used"
+                ))
+            }
+        };
+        let keycode = self.spare[index].0;
+        //Every column gets the keysym, so the modifiers that are held do not change it
+        let keysyms = vec![keysym; self.keymap.keysyms_per_keycode];
+        self.conn
+            .send_and_check_request(&xcb::x::ChangeKeyboardMapping {
+                keycode_count: 1,
+                first_keycode: keycode,
+                keysyms_per_keycode: self.keymap.keysyms_per_keycode as u8,
+                keysyms: &keysyms,
+            })
+            .map_err(|a| format!("Not able to remap keycode {keycode}: {a}"))?;
+        //The round trip makes sure the server uses the new mapping before the key is faked
+        self.conn
+            .wait_for_reply(self.conn.send_request(&xcb::x::GetInputFocus {}))
+            .map_err(|a| format!("Not able to remap keycode {keycode}: {a}"))?;
+        self.spare[index].1 = Some(keysym);
+        self.next_spare = (index + 1) % count;
+        Ok(keycode)
+    }
+
+    //Reads the keymap again when the layout changed, the MappingNotify of our own remapping included
+    fn poll_mapping(&mut self) {
+        let mut changed = false;
+        while let Ok(Some(event)) = self.conn.poll_for_event() {
+            if let xcb::Event::X(xcb::x::Event::MappingNotify(a)) = event {
+                changed |= a.request() == xcb::x::Mapping::Keyboard;
+            }
+        }
+        if !changed {
+            return;
+        }
+        if let Ok(mut keymap) = load_keymap(&self.conn) {
+            for (keycode, _) in &self.spare {
+                keymap.forget(*keycode);
+            }
+            self.keymap = keymap;
+        }
+    }
+
+    pub fn inject(&mut self, event: DesktopPointer) -> Result<(), String> {
+        match event {
+            DesktopPointer::Press(button) if !self.buttons.contains(&button) => self.buttons.push(button),
+            DesktopPointer::Release(button) => self.buttons.retain(|a| *a != button),
+            _ => {}
+        }
+        let result = match event {
+            DesktopPointer::MoveTo(x, y) => self.fake(MOTION_NOTIFY, 0, x, y),
+            //Detail 1 makes the motion relative to where the pointer is
+            DesktopPointer::MoveBy(dx, dy) => self.fake(MOTION_NOTIFY, 1, dx, dy),
+            DesktopPointer::Press(button) => self.fake(BUTTON_PRESS, button, 0, 0),
+            DesktopPointer::Release(button) => self.fake(BUTTON_RELEASE, button, 0, 0),
+            DesktopPointer::Scroll(dx, dy) => self.scroll(dx, dy),
+        };
+        result.map_err(|a| format!("Not able to inject {event:?}: {a}"))
+    }
+
+    //Releases the keys and buttons the receiver left held down, it can not release them once it is gone
+    pub fn release_all(&mut self) -> Result<(), String> {
+        let mut result = Ok(());
+        for keycode in std::mem::take(&mut self.pressed) {
+            if let Err(a) = self.fake(KEY_RELEASE, keycode, 0, 0) {
+                result = Err(format!("Not able to release keycode {keycode}: {a}"));
+            }
+        }
+        for button in std::mem::take(&mut self.buttons) {
+            if let Err(a) = self.fake(BUTTON_RELEASE, button, 0, 0) {
+                result = Err(format!("Not able to release button {button}: {a}"));
+            }
+        }
+        result
+    }
+
+    //Every wheel step is a click of button 4 (up), 5 (down), 6 (left) or 7 (right)
+    fn scroll(&self, dx: i32, dy: i32) -> xcb::ProtocolResult<()> {
+        for (steps, negative, positive) in [(dy, 4, 5), (dx, 6, 7)] {
+            let button = if steps < 0 { negative } else { positive };
+            for _ in 0..steps.abs().min(MAX_SCROLL_STEPS) {
+                self.fake(BUTTON_PRESS, button, 0, 0)?;
+                self.fake(BUTTON_RELEASE, button, 0, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn fake(&self, kind: u8, detail: u8, x: i32, y: i32) -> xcb::ProtocolResult<()> {
+        let clamp = |a: i32| a.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        self.conn.send_and_check_request(&xcb::xtest::FakeInput {
+            r#type: kind,
+            detail,
+            time: xcb::x::CURRENT_TIME,
+            root: self.root,
+            root_x: clamp(x),
+            root_y: clamp(y),
+            deviceid: 0,
+        })
+    }
+}
+
+//Nothing stays held and the remapped spare keycodes go back to typing nothing
+#[cfg(target_os = "linux")]
+impl Drop for InputInjector {
+    fn drop(&mut self) {
+        let _ = self.release_all();
+        let keysyms = vec![0; self.keymap.keysyms_per_keycode];
+        for (keycode, _) in self.spare.iter().filter(|a| a.1.is_some()) {
+            let _ = self.conn.send_and_check_request(&xcb::x::ChangeKeyboardMapping {
+                keycode_count: 1,
+                first_keycode: *keycode,
+                keysyms_per_keycode: self.keymap.keysyms_per_keycode as u8,
+                keysyms: &keysyms,
+            });
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn load_keymap(conn: &xcb::Connection) -> xcb::Result<Keymap> {
+    let setup = conn.get_setup();
+    let (min_keycode, max_keycode) = (setup.min_keycode(), setup.max_keycode());
+    let reply = conn.wait_for_reply(conn.send_request(&xcb::x::GetKeyboardMapping {
+        first_keycode: min_keycode,
+        count: max_keycode - min_keycode + 1,
+    }))?;
+    Ok(Keymap {
+        min_keycode,
+        keysyms_per_keycode: reply.keysyms_per_keycode() as usize,
+        keysyms: reply.keysyms().to_vec(),
+    })
+}
+
+//XTest only exists on X11, the receiver can only watch on Windows
+#[cfg(target_os = "windows")]
+pub struct InputInjector;
+
+#[cfg(target_os = "windows")]
+impl InputInjector {
+    pub fn new(config: &HashMap<String, String>) -> Result<Self, GabinatorError> {
+        Err(GabinatorError::newCapture(
+            "Input from the receiver is only supported on X11",
+            LoggerLevel::Warning,
+            Some(config.clone()),
+        ))
+    }
+
+    pub fn inject(&mut self, _event: DesktopPointer) -> Result<(), String> {
+        Err("Input from the receiver is only supported on X11".to_string())
+    }
+
+    pub fn release_all(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    pub fn key(&mut self, _keysym: u32, _pressed: bool) -> Result<(), String> {
+        Err("Input from the receiver is only supported on X11".to_string())
+    }
+
+    pub fn type_text(&mut self, _text: &str) -> Result<(), String> {
+        Err("Input from the receiver is only supported on X11".to_string())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    //Keycodes 8 to 11 with two keysyms each, 10 is free
+    fn keymap() -> Keymap {
+        Keymap {
+            min_keycode: 8,
+            keysyms_per_keycode: 2,
+            keysyms: vec![0x61, 0x41, 0x31, 0x21, 0, 0, XK_SHIFT_L, 0],
+        }
+    }
+
+    #[test]
+    fn finds_the_column() {
+        let keymap = keymap();
+        assert_eq!(keymap.find('a' as u32), Some((8, false)));
+        assert_eq!(keymap.find('!' as u32), Some((9, true)));
+        assert_eq!(keymap.find(XK_SHIFT_L), Some((11, false)));
+        assert_eq!(keymap.find('z' as u32), None);
+    }
+
+    #[test]
+    fn spare_keycodes() {
+        let mut keymap = keymap();
+        assert_eq!(keymap.unused(), vec![10]);
+        keymap.forget(8);
+        assert_eq!(keymap.unused(), vec![8, 10]);
+        assert_eq!(keymap.find('a' as u32), None);
+    }
+
+    #[test]
+    fn keysyms_of_characters() {
+        assert_eq!(char_keysym('a'), Some(0x61));
+        assert_eq!(char_keysym('é'), Some(0xe9));
+        assert_eq!(char_keysym('€'), Some(0x010020ac));
+        assert_eq!(char_keysym('\n'), Some(XK_RETURN));
+        assert_eq!(char_keysym('\u{7}'), None);
+    }
+}
@@ -0,0 +1,206 @@
+use std::ffi::CStr;
+use std::str::FromStr;
+
+use turbojpeg::raw;
+
+use crate::encoder::{BufferPool, Codec, Encoder};
+use crate::frame::{Frame, PixelFormat};
+
+//How much color information is kept, 4:2:0 halves it both ways and smears colored text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Subsampling {
+    S444,
+    S422,
+    S420,
+    Gray,
+}
+
+impl Subsampling {
+    fn turbojpeg(self) -> turbojpeg::Subsamp {
+        match self {
+            Subsampling::S444 => turbojpeg::Subsamp::None,
+            Subsampling::S422 => turbojpeg::Subsamp::Sub2x1,
+            Subsampling::S420 => turbojpeg::Subsamp::Sub2x2,
+            Subsampling::Gray => turbojpeg::Subsamp::Gray,
+        }
+    }
+}
+
+impl FromStr for Subsampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "444" | "4:4:4" => Ok(Subsampling::S444),
+            "422" | "4:2:2" => Ok(Subsampling::S422),
+            "420" | "4:2:0" => Ok(Subsampling::S420),
+            "gray" | "grey" => Ok(Subsampling::Gray),
+            _ => Err(format!("Not a valid subsampling {s}, use 444, 422, 420 or gray")),
+        }
+    }
+}
+
+//Everything that changes how libjpeg-turbo writes the frames
+#[derive(Clone, Copy, Debug)]
+pub struct JpegOptions {
+    pub subsampling: Subsampling,
+    //Computes the Huffman tables of every frame, a bit smaller but slower
+    pub optimize: bool,
+    pub progressive: bool,
+    //MCU rows between restart markers, 0 disables them. They let the decoder skip a corrupted part
+    pub restart_rows: u16,
+}
+
+impl Default for JpegOptions {
+    fn default() -> Self {
+        JpegOptions {
+            subsampling: Subsampling::S420,
+            optimize: false,
+            progressive: false,
+            restart_rows: 0,
+        }
+    }
+}
+
+fn turbojpeg_format(format: PixelFormat) -> turbojpeg::PixelFormat {
+    match format {
+        PixelFormat::Rgba => turbojpeg::PixelFormat::RGBA,
+        PixelFormat::Rgbx => turbojpeg::PixelFormat::RGBX,
+        PixelFormat::Bgrx => turbojpeg::PixelFormat::BGRX,
+        PixelFormat::Xrgb => turbojpeg::PixelFormat::XRGB,
+        PixelFormat::Xbgr => turbojpeg::PixelFormat::XBGR,
+        PixelFormat::Rgb => turbojpeg::PixelFormat::RGB,
+        PixelFormat::Bgr => turbojpeg::PixelFormat::BGR,
+    }
+}
+
+//libjpeg-turbo compressor, used through the raw API because the turbojpeg crate
+//has no setters for progressive mode and restart markers
+struct Compressor {
+    handle: raw::tjhandle,
+}
+
+impl Compressor {
+    fn new() -> Result<Self, String> {
+        //SAFETY: tj3Init has no preconditions, the handle is checked before being used
+        let handle = unsafe { raw::tj3Init(raw::TJINIT_TJINIT_COMPRESS as _) };
+        if handle.is_null() {
+            return Err("Not able to create the libjpeg-turbo compressor".to_string());
+        }
+        Ok(Compressor { handle })
+    }
+
+    fn error(&self) -> String {
+        //SAFETY: the handle is valid and the message is a C string owned by libjpeg-turbo
+        unsafe { CStr::from_ptr(raw::tj3GetErrorStr(self.handle)) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn set(&mut self, param: raw::TJPARAM, value: i32) -> Result<(), String> {
+        //SAFETY: the handle is valid, bad parameters are reported through the return value
+        if unsafe { raw::tj3Set(self.handle, param as _, value) } != 0 {
+            return Err(self.error());
+        }
+        Ok(())
+    }
+
+    //Compresses into output without ever reallocating it, returns the JPEG size
+    fn compress(&mut self, frame: &Frame, output: &mut [u8]) -> Result<usize, String> {
+        let bytes_per_pixel = frame.format.bytes_per_pixel();
+        let needed = frame.pitch * frame.height.saturating_sub(1) as usize + frame.width as usize * bytes_per_pixel;
+        if frame.data.len() < needed {
+            return Err(format!("The frame has {} bytes, {needed} are needed", frame.data.len()));
+        }
+        let mut pointer = output.as_mut_ptr();
+        let mut len = output.len() as raw::size_t;
+        //SAFETY: the source size was checked above and NOREALLOC keeps libjpeg-turbo
+        //inside the len bytes of output
+        let result = unsafe {
+            raw::tj3Compress8(
+                self.handle,
+                frame.data.as_ptr(),
+                frame.width as _,
+                frame.pitch as _,
+                frame.height as _,
+                turbojpeg_format(frame.format) as _,
+                &mut pointer,
+                &mut len,
+            )
+        };
+        if result != 0 {
+            return Err(self.error());
+        }
+        Ok(len as usize)
+    }
+}
+
+impl Drop for Compressor {
+    fn drop(&mut self) {
+        //SAFETY: the handle was created by tj3Init and is not used after this
+        unsafe { raw::tj3Destroy(self.handle) };
+    }
+}
+
+//Long lived JPEG encoder, the libjpeg-turbo handle and the output buffers are reused between frames
+pub struct JpegEncoder {
+    compressor: Compressor,
+    options: JpegOptions,
+    pool: BufferPool,
+}
+
+impl JpegEncoder {
+    pub fn new(quality: u8, options: JpegOptions) -> Result<Self, String> {
+        let mut compressor = Compressor::new()?;
+        compressor.set(raw::TJPARAM_TJPARAM_QUALITY, quality.into())?;
+        compressor.set(raw::TJPARAM_TJPARAM_SUBSAMP, options.subsampling.turbojpeg() as i32)?;
+        compressor.set(raw::TJPARAM_TJPARAM_OPTIMIZE, options.optimize as i32)?;
+        compressor.set(raw::TJPARAM_TJPARAM_PROGRESSIVE, options.progressive as i32)?;
+        compressor.set(raw::TJPARAM_TJPARAM_RESTARTROWS, options.restart_rows.into())?;
+        compressor.set(raw::TJPARAM_TJPARAM_NOREALLOC, 1)?;
+        Ok(JpegEncoder {
+            compressor,
+            options,
+            pool: BufferPool::default(),
+        })
+    }
+}
+
+impl Encoder for JpegEncoder {
+    //Gray frames are told apart, so the receiver does not have to look inside them
+    fn codec(&self) -> Codec {
+        match self.options.subsampling {
+            Subsampling::Gray => Codec::JpegGray,
+            _ => Codec::Jpeg,
+        }
+    }
+
+    fn encode(&mut self, frame: &Frame) -> Result<Vec<u8>, String> {
+        //Worst case size, so libjpeg-turbo never has to grow the buffer by itself
+        let len = turbojpeg::compressed_buf_len(
+            frame.width as usize,
+            frame.height as usize,
+            self.options.subsampling.turbojpeg(),
+        )
+        .map_err(|a| a.to_string())?;
+        let mut buffer = self.pool.take(len);
+        match self.compressor.compress(frame, &mut buffer) {
+            Ok(a) => {
+                buffer.truncate(a);
+                Ok(buffer)
+            }
+            Err(a) => {
+                self.pool.give_back(buffer);
+                Err(a)
+            }
+        }
+    }
+
+    fn recycle(&mut self, buffer: Vec<u8>) {
+        self.pool.give_back(buffer);
+    }
+
+    fn set_quality(&mut self, quality: u8) -> Result<(), String> {
+        self.compressor.set(raw::TJPARAM_TJPARAM_QUALITY, quality.into())
+    }
+}
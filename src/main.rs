@@ -2,15 +2,43 @@ use std::{
     env,
     u8,
 };
+mod adaptive;
+mod budget;
 mod capture;
+mod clipboard;
+#[cfg(target_os = "linux")]
+mod convert;
+#[cfg(target_os = "linux")]
+mod cursor;
+#[cfg(target_os = "linux")]
+mod damage;
+mod encoder;
 pub mod error;
+mod frame;
+mod h264;
+mod handshake;
+mod input;
+mod jpeg;
+mod message;
+mod pipeline;
+mod protocol;
+mod raw;
+mod scale;
+mod source;
+mod tiles;
+mod transform;
 mod usb;
 mod mod_aoa;
-use capture::capture_screen;
+use capture::{CaptureOptions, CaptureTarget};
+use adaptive::Target;
+use encoder::{Codec, EncoderOptions};
+use source::SourceKind;
+use window::WindowSelector;
 use error::{GabinatorError, Logger, LoggerLevel};
 use rusb::{DeviceHandle, GlobalContext};
 use usb::{capture_and_send, find_compatible_usb};
 mod tcp;
+mod window;
 fn main() {
     let config = Logger::get_config_content();
     //TEST
@@ -29,7 +57,240 @@ fn main() {
     let mut device = None;
     let mut test_tcp = false;
     let mut test_data = false;
-    let mut quality = 25;
+    let config_codec = match config.get("codec") {
+        Some(a) => match a.parse() {
+            Ok(a) => a,
+            Err(a) => {
+                GabinatorError::newMain(a, LoggerLevel::Error, Some(config.clone()));
+                Codec::Jpeg
+            }
+        },
+        None => Codec::Jpeg,
+    };
+    let mut encoder_options = EncoderOptions {
+        codec: config_codec,
+        ..Default::default()
+    };
+    if let Some(a) = config.get("jpeg_subsampling") {
+        match a.parse() {
+            Ok(b) => encoder_options.jpeg.subsampling = b,
+            Err(b) => {
+                GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+            }
+        }
+    }
+    if let Some(a) = config.get("tiles") {
+        match a.parse() {
+            Ok(b) => encoder_options.tiles = Some(b),
+            Err(b) => {
+                GabinatorError::newMain(
+                    format!("Not a valid tile size in the config: {b}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                );
+            }
+        }
+    }
+    encoder_options.jpeg.optimize = config.get("jpeg_optimize").map(|a| a == "true").unwrap_or(false);
+    encoder_options.jpeg.progressive = config.get("jpeg_progressive").map(|a| a == "true").unwrap_or(false);
+    if let Some(a) = config.get("jpeg_restart") {
+        match a.parse() {
+            Ok(b) => encoder_options.jpeg.restart_rows = b,
+            Err(b) => {
+                GabinatorError::newMain(
+                    format!("Not a valid restart interval in the config: {b}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                );
+            }
+        }
+    }
+    if let Some(a) = config.get("h264_keyframe_interval") {
+        match a.parse() {
+            Ok(b) => encoder_options.h264.keyframe_interval = b,
+            Err(b) => {
+                GabinatorError::newMain(
+                    format!("Not a valid keyframe interval in the config: {b}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                );
+            }
+        }
+    }
+    if let Some(a) = config.get("h264_bitrate") {
+        match a.parse::<u32>() {
+            Ok(b) => encoder_options.h264.bitrate = b.saturating_mul(1000),
+            Err(b) => {
+                GabinatorError::newMain(
+                    format!("Not a valid bitrate in the config: {b}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                );
+            }
+        }
+    }
+    if let Some(a) = config.get("target_fps") {
+        match a.parse::<f32>() {
+            Ok(b) => encoder_options.adaptive.target = Some(Target::Fps(b)),
+            Err(b) => {
+                GabinatorError::newMain(
+                    format!("Not a valid target frame rate in the config: {b}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                );
+            }
+        }
+    }
+    if let Some(a) = config.get("target_bitrate") {
+        if config.contains_key("target_fps") {
+            GabinatorError::newMain(
+                "Both target_fps and target_bitrate are in the config, only target_bitrate is used",
+                LoggerLevel::Warning,
+                Some(config.clone()),
+            );
+        }
+        match a.parse::<u32>() {
+            Ok(b) => encoder_options.adaptive.target = Some(Target::Bitrate(b.saturating_mul(1000))),
+            Err(b) => {
+                GabinatorError::newMain(
+                    format!("Not a valid target bitrate in the config: {b}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                );
+            }
+        }
+    }
+    if let Some(a) = config.get("min_quality") {
+        match a.parse() {
+            Ok(b) => encoder_options.adaptive.min_quality = b,
+            Err(b) => {
+                GabinatorError::newMain(
+                    format!("Not a valid minimum quality in the config: {b}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                );
+            }
+        }
+    }
+    if let Some(a) = config.get("max_quality") {
+        match a.parse() {
+            Ok(b) => encoder_options.adaptive.max_quality = b,
+            Err(b) => {
+                GabinatorError::newMain(
+                    format!("Not a valid maximum quality in the config: {b}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                );
+            }
+        }
+    }
+    if let Some(a) = config.get("min_scale") {
+        match a.parse::<u8>() {
+            Ok(b) => encoder_options.adaptive.min_scale = b as f32 / 100.0,
+            Err(b) => {
+                GabinatorError::newMain(
+                    format!("Not a valid minimum scale in the config: {b}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                );
+            }
+        }
+    }
+    if let Some(a) = config.get("max_frame_size") {
+        match a.parse() {
+            Ok(b) => encoder_options.max_frame_size = Some(b),
+            Err(b) => {
+                GabinatorError::newMain(
+                    format!("Not a valid maximum frame size in the config: {b}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                );
+            }
+        }
+    }
+    if let Some(a) = config.get("output_size") {
+        match a.parse() {
+            Ok(b) => encoder_options.output.size = Some(b),
+            Err(b) => {
+                GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+            }
+        }
+    }
+    if let Some(a) = config.get("output_mode") {
+        match a.parse() {
+            Ok(b) => encoder_options.output.mode = b,
+            Err(b) => {
+                GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+            }
+        }
+    }
+    if let Some(a) = config.get("output_filter") {
+        match a.parse() {
+            Ok(b) => encoder_options.output.filter = b,
+            Err(b) => {
+                GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+            }
+        }
+    }
+    if let Some(a) = config.get("letterbox") {
+        match a.parse() {
+            Ok(b) => encoder_options.output.letterbox = b,
+            Err(b) => {
+                GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+            }
+        }
+    }
+    if let Some(a) = config.get("rotation") {
+        match a.parse() {
+            Ok(b) => encoder_options.transform.rotation = b,
+            Err(b) => {
+                GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+            }
+        }
+    }
+    encoder_options.transform.flip_horizontal = config.get("flip_horizontal").map(|a| a == "true").unwrap_or(false);
+    encoder_options.transform.flip_vertical = config.get("flip_vertical").map(|a| a == "true").unwrap_or(false);
+    if let Some(a) = config.get("dither") {
+        match a.parse() {
+            Ok(b) => encoder_options.dither = b,
+            Err(b) => {
+                GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+            }
+        }
+    }
+    let config_target = match config.get("region") {
+        Some(a) => match a.parse() {
+            Ok(a) => CaptureTarget::Region(a),
+            Err(a) => {
+                GabinatorError::newMain(
+                    format!("Not a valid region in the config: {a}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                );
+                CaptureTarget::Screen
+            }
+        },
+        None => CaptureTarget::Screen,
+    };
+    let config_source = match config.get("source") {
+        Some(a) => match a.parse() {
+            Ok(a) => a,
+            Err(a) => {
+                GabinatorError::newMain(a, LoggerLevel::Error, Some(config.clone()));
+                SourceKind::Screen
+            }
+        },
+        None => SourceKind::Screen,
+    };
+    let mut options = CaptureOptions {
+        source: config_source,
+        target: config_target,
+        cursor: config.get("cursor").map(|a| a == "true").unwrap_or(false),
+        input: config.get("input").map(|a| a == "true").unwrap_or(false),
+        clipboard: config.get("clipboard").map(|a| a == "true").unwrap_or(false),
+        clipboard_images: config.get("clipboard_images").map(|a| a == "true").unwrap_or(false),
+        ..Default::default()
+    };
 
     parse_arg(
         &args,
@@ -48,6 +309,41 @@ fn main() {
                     -h / --help: Print this message\n
                     -M / --mode: Set the mode, it can be AOA or TCP\n
                     -Q / --quality: Set the quality of the image\n 
+                    -cd / --codec: Set the codec of the frames: jpeg, png, webp, qoi, h264, jpeg-gray, gray4, mono or rgb565 (or the codec key of the config)\n
+                    -js / --jpeg-subsampling: JPEG chroma subsampling: 444, 422, 420 or gray (or the jpeg_subsampling key of the config)\n
+                    -jo / --jpeg-optimize: Compute optimized Huffman tables for every JPEG frame\n
+                    -jp / --jpeg-progressive: Send progressive JPEG frames\n
+                    -jr / --jpeg-restart: MCU rows between JPEG restart markers, 0 disables them (or the jpeg_restart key of the config)\n
+                    -di / --dither: Dithering of the gray4 and mono codecs: floyd or ordered (or the dither key of the config)\n
+                    -kf / --keyframe-interval: Frames between H.264 keyframes, 0 sends them only for new clients (or the h264_keyframe_interval key of the config)\n
+                    -br / --bitrate: H.264 bitrate in kbit/s (or the h264_bitrate key of the config)\n
+                    -tf / --target-fps: Move the quality to send this many frames per second (or the target_fps key of the config)\n
+                    -tb / --target-bitrate: Move the quality to send this many kbit/s (or the target_bitrate key of the config)\n
+                    -qn / --min-quality: Lowest quality the target can lower it to (or the min_quality key of the config)\n
+                    -qx / --max-quality: Highest quality the target can raise it to (or the max_quality key of the config)\n
+                    -ms / --min-scale: Smallest resolution in percent the target can shrink the frames to, 100 keeps it (or the min_scale key of the config)\n
+                    -mf / --max-frame-size: Largest frame in bytes, the highest quality and scale that fit are used (or the max_frame_size key of the config)\n
+                    -ro / --rotation: Rotate the frames clockwise 0, 90, 180 or 270 degrees, the receiver can change it (or the rotation key of the config)\n
+                    -fh / --flip-horizontal: Mirror the frames left to right (or the flip_horizontal key of the config)\n
+                    -fv / --flip-vertical: Mirror the frames top to bottom (or the flip_vertical key of the config)\n
+                    -os / --output-size: Resize the frames to widthxheight, the receiver can change it (or the output_size key of the config)\n
+                    -om / --output-mode: How the frames fit the output size: fit, fill or stretch (or the output_mode key of the config)\n
+                    -of / --output-filter: Resize filter: nearest, bilinear or lanczos (or the output_filter key of the config)\n
+                    -lb / --letterbox: RRGGBB color around the frames in fit mode (or the letterbox key of the config)\n
+                    -ti / --tiles: Send only the tiles of this size that changed since the last acknowledged frame (or the tiles key of the config)\n
+                    -src / --source: Where the frames come from: screen, pattern or dir:<path> (or the source key of the config)\n
+                    -LM / --list-monitors: Prints the RandR monitors that can be captured\n
+                    -m / --monitor: Capture only one monitor, by name or index\n
+                    -r / --region: Capture only the rectangle x,y,width,height (or the region key of the config)\n
+                    -w / --window: Capture only the window with this X ID\n
+                    -wt / --window-title: Capture only the first window whose title contains this text\n
+                    -wp / --window-process: Capture only the first window of this PID or process name\n
+                    -nc / --no-composite: Read windows from the screen instead of their XComposite pixmap\n
+                    -nd / --no-damage: Send every frame, even if nothing changed on the screen\n
+                    -cu / --cursor: Draw the mouse pointer over the frames (or the cursor key of the config)\n
+                    -in / --input: Let the receiver move the pointer, click, scroll and type with XTest (or the input key of the config)\n
+                    -cb / --clipboard: Synchronize the CLIPBOARD and PRIMARY selections with the receiver (or the clipboard key of the config)\n
+                    -ci / --clipboard-images: Synchronize PNG images too, not only text (or the clipboard_images key of the config)\n
                     -C / --connect: Start the server\n");
 
             true
@@ -208,7 +504,7 @@ fn main() {
         "--quality".to_string(),
         true,
         |a: &String| -> bool {
-            quality = match a.parse::<u8>() {
+            encoder_options.quality = match a.parse::<u8>() {
                 Ok(b) => b,
                 Err(b) => {
                     GabinatorError::newMain(
@@ -216,13 +512,580 @@ fn main() {
                         LoggerLevel::Error,
                         Some(config.clone()),
                     );
-                    quality
+                    encoder_options.quality
                 }
             };
             return true;
         },
     );
 
+    parse_arg(
+        &args,
+        "-cd".to_string(),
+        "--codec".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.codec = match a.parse() {
+                Ok(b) => b,
+                Err(b) => {
+                    GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-js".to_string(),
+        "--jpeg-subsampling".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.jpeg.subsampling = match a.parse() {
+                Ok(b) => b,
+                Err(b) => {
+                    GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-jo".to_string(),
+        "--jpeg-optimize".to_string(),
+        false,
+        |_a: &String| -> bool {
+            encoder_options.jpeg.optimize = true;
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-jp".to_string(),
+        "--jpeg-progressive".to_string(),
+        false,
+        |_a: &String| -> bool {
+            encoder_options.jpeg.progressive = true;
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-jr".to_string(),
+        "--jpeg-restart".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.jpeg.restart_rows = match a.parse() {
+                Ok(b) => b,
+                Err(b) => {
+                    GabinatorError::newMain(
+                        format!("Not a valid restart interval {b}"),
+                        LoggerLevel::Error,
+                        Some(config.clone()),
+                    );
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-di".to_string(),
+        "--dither".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.dither = match a.parse() {
+                Ok(b) => b,
+                Err(b) => {
+                    GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-kf".to_string(),
+        "--keyframe-interval".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.h264.keyframe_interval = match a.parse() {
+                Ok(b) => b,
+                Err(b) => {
+                    GabinatorError::newMain(
+                        format!("Not a valid keyframe interval {b}"),
+                        LoggerLevel::Error,
+                        Some(config.clone()),
+                    );
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-br".to_string(),
+        "--bitrate".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.h264.bitrate = match a.parse::<u32>() {
+                Ok(b) => b.saturating_mul(1000),
+                Err(b) => {
+                    GabinatorError::newMain(format!("Not a valid bitrate {b}"), LoggerLevel::Error, Some(config.clone()));
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    //Only one target can be reached
+    let has_arg = |short: &str, long: &str| args.iter().any(|a| a == short || a == long);
+    if has_arg("-tf", "--target-fps") && has_arg("-tb", "--target-bitrate") {
+        GabinatorError::newMain(
+            "Both --target-fps and --target-bitrate are given, only --target-bitrate is used",
+            LoggerLevel::Warning,
+            Some(config.clone()),
+        );
+    }
+
+    parse_arg(
+        &args,
+        "-tf".to_string(),
+        "--target-fps".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.adaptive.target = match a.parse::<f32>() {
+                Ok(b) => Some(Target::Fps(b)),
+                Err(b) => {
+                    GabinatorError::newMain(
+                        format!("Not a valid target frame rate {b}"),
+                        LoggerLevel::Error,
+                        Some(config.clone()),
+                    );
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-tb".to_string(),
+        "--target-bitrate".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.adaptive.target = match a.parse::<u32>() {
+                Ok(b) => Some(Target::Bitrate(b.saturating_mul(1000))),
+                Err(b) => {
+                    GabinatorError::newMain(
+                        format!("Not a valid target bitrate {b}"),
+                        LoggerLevel::Error,
+                        Some(config.clone()),
+                    );
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-qn".to_string(),
+        "--min-quality".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.adaptive.min_quality = match a.parse() {
+                Ok(b) => b,
+                Err(b) => {
+                    GabinatorError::newMain(
+                        format!("Not a valid minimum quality {b}"),
+                        LoggerLevel::Error,
+                        Some(config.clone()),
+                    );
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-qx".to_string(),
+        "--max-quality".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.adaptive.max_quality = match a.parse() {
+                Ok(b) => b,
+                Err(b) => {
+                    GabinatorError::newMain(
+                        format!("Not a valid maximum quality {b}"),
+                        LoggerLevel::Error,
+                        Some(config.clone()),
+                    );
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-ms".to_string(),
+        "--min-scale".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.adaptive.min_scale = match a.parse::<u8>() {
+                Ok(b) => b as f32 / 100.0,
+                Err(b) => {
+                    GabinatorError::newMain(
+                        format!("Not a valid minimum scale {b}"),
+                        LoggerLevel::Error,
+                        Some(config.clone()),
+                    );
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-mf".to_string(),
+        "--max-frame-size".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.max_frame_size = match a.parse() {
+                Ok(b) => Some(b),
+                Err(b) => {
+                    GabinatorError::newMain(
+                        format!("Not a valid maximum frame size {b}"),
+                        LoggerLevel::Error,
+                        Some(config.clone()),
+                    );
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-os".to_string(),
+        "--output-size".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.output.size = match a.parse() {
+                Ok(b) => Some(b),
+                Err(b) => {
+                    GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-om".to_string(),
+        "--output-mode".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.output.mode = match a.parse() {
+                Ok(b) => b,
+                Err(b) => {
+                    GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-of".to_string(),
+        "--output-filter".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.output.filter = match a.parse() {
+                Ok(b) => b,
+                Err(b) => {
+                    GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-lb".to_string(),
+        "--letterbox".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.output.letterbox = match a.parse() {
+                Ok(b) => b,
+                Err(b) => {
+                    GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-ro".to_string(),
+        "--rotation".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.transform.rotation = match a.parse() {
+                Ok(b) => b,
+                Err(b) => {
+                    GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-fh".to_string(),
+        "--flip-horizontal".to_string(),
+        false,
+        |_a: &String| -> bool {
+            encoder_options.transform.flip_horizontal = true;
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-fv".to_string(),
+        "--flip-vertical".to_string(),
+        false,
+        |_a: &String| -> bool {
+            encoder_options.transform.flip_vertical = true;
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-ti".to_string(),
+        "--tiles".to_string(),
+        true,
+        |a: &String| -> bool {
+            encoder_options.tiles = match a.parse() {
+                Ok(b) => Some(b),
+                Err(b) => {
+                    GabinatorError::newMain(
+                        format!("Not a valid tile size {b}"),
+                        LoggerLevel::Error,
+                        Some(config.clone()),
+                    );
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-src".to_string(),
+        "--source".to_string(),
+        true,
+        |a: &String| -> bool {
+            options.source = match a.parse() {
+                Ok(b) => b,
+                Err(b) => {
+                    GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-LM".to_string(),
+        "--list-monitors".to_string(),
+        false,
+        |_a: &String| match capture::list_monitors() {
+            Ok(a) => {
+                for monitor in a {
+                    println!("{monitor}");
+                }
+                true
+            }
+            Err(_) => false,
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-m".to_string(),
+        "--monitor".to_string(),
+        true,
+        |a: &String| -> bool {
+            options.target = CaptureTarget::Monitor(a.clone());
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-r".to_string(),
+        "--region".to_string(),
+        true,
+        |a: &String| -> bool {
+            options.target = match a.parse() {
+                Ok(b) => CaptureTarget::Region(b),
+                Err(b) => {
+                    GabinatorError::newMain(
+                        format!("Not a valid region parameter {b}"),
+                        LoggerLevel::Error,
+                        Some(config.clone()),
+                    );
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-w".to_string(),
+        "--window".to_string(),
+        true,
+        |a: &String| -> bool {
+            options.target = match window::parse_window_id(a) {
+                Ok(b) => CaptureTarget::Window(WindowSelector::Id(b)),
+                Err(b) => {
+                    GabinatorError::newMain(b, LoggerLevel::Error, Some(config.clone()));
+                    return false;
+                }
+            };
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-wt".to_string(),
+        "--window-title".to_string(),
+        true,
+        |a: &String| -> bool {
+            options.target = CaptureTarget::Window(WindowSelector::Title(a.clone()));
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-wp".to_string(),
+        "--window-process".to_string(),
+        true,
+        |a: &String| -> bool {
+            options.target = CaptureTarget::Window(WindowSelector::Process(a.clone()));
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-nc".to_string(),
+        "--no-composite".to_string(),
+        false,
+        |_a: &String| -> bool {
+            options.composite = false;
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-nd".to_string(),
+        "--no-damage".to_string(),
+        false,
+        |_a: &String| -> bool {
+            options.damage = false;
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-cu".to_string(),
+        "--cursor".to_string(),
+        false,
+        |_a: &String| -> bool {
+            options.cursor = true;
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-in".to_string(),
+        "--input".to_string(),
+        false,
+        |_a: &String| -> bool {
+            options.input = true;
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-cb".to_string(),
+        "--clipboard".to_string(),
+        false,
+        |_a: &String| -> bool {
+            options.clipboard = true;
+            true
+        },
+    );
+
+    parse_arg(
+        &args,
+        "-ci".to_string(),
+        "--clipboard-images".to_string(),
+        false,
+        |_a: &String| -> bool {
+            options.clipboard = true;
+            options.clipboard_images = true;
+            true
+        },
+    );
+
     //Connect to the device and send capture
     parse_arg(
         &args,
@@ -233,7 +1096,7 @@ fn main() {
             match mode {
                 0 => {
                     if vid > 0 && pid > 0 {
-                        match usb::connect_to_device(pid, vid, quality) {
+                        match usb::connect_to_device(pid, vid, encoder_options.clone(), options.clone()) {
                             Ok(a) => Logger::log(
                                 format!("Connected"),
                                 LoggerLevel::Info,
@@ -249,7 +1112,7 @@ fn main() {
                 }
 
                 1 => {
-                    tcp::start_server(test_tcp, test_data, quality);
+                    tcp::start_server(test_tcp, test_data, encoder_options.clone(), options.clone());
                 }
 
                 _ => panic!("NOT VALID MODE"),
@@ -306,7 +1169,7 @@ fn main() {
         false,
         |a: &String| -> bool {
             if pid > 0 && vid > 0 && endpoint > 0 && device.is_some() {
-                match usb::capture_and_send(&device.as_mut().unwrap(), endpoint, quality) {
+                match usb::capture_and_send(&device.as_mut().unwrap(), endpoint, &encoder_options, options.clone()) {
                     Some(a) => {
                         let _b = GabinatorError::newMain(
                             "Error sending image {a}",
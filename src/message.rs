@@ -0,0 +1,389 @@
+use crate::encoder::{Codec, EncoderOptions};
+use crate::scale::Size;
+use crate::transform::Rotation;
+
+//Control messages share the stream with the frames, they start with a 0 byte that no frame starts with
+//[0][kind][payload length, u32 big endian][payload]
+const HEADER_LEN: usize = 6;
+
+//Largest clipboard content that is synchronized, bigger content would hold the frames back for too long
+pub const MAX_CONTENT: usize = 8 << 20;
+//Largest payload read from the receiver, a clipboard message has 2 bytes before its content
+const MAX_PAYLOAD: usize = MAX_CONTENT + 2;
+
+//Server to receiver, the codec of the next frames
+pub const CODEC: u8 = 1;
+//Server to receiver, the tiles that changed since the last acknowledged frame
+//[update id u32][frame width u16][frame height u16][tile count u32], then for every tile
+//[x u16][y u16][width u16][height u16][encoded length u32][encoded tile]
+pub const TILE_UPDATE: u8 = 2;
+//Receiver to server, a tile update was received and drawn
+pub const ACK: u8 = 3;
+//Receiver to server, the size it wants the frames in, [width u16][height u16], 0x0 for the captured size
+pub const RESOLUTION: u8 = 4;
+//Receiver to server, its orientation changed, [clockwise quarter turns the frames need u8]
+pub const ORIENTATION: u8 = 5;
+//Receiver to server, first message after connecting, what the receiver can do
+//[protocol version u8][screen width u16][screen height u16][max frame size u32, 0 for none][codec count u8][codec ids u8...]
+pub const HELLO: u8 = 6;
+//Server to receiver, answer to the hello with what was chosen
+//[protocol version u8][codec u8][width u16][height u16, 0x0 for the captured size][max frame size u32, 0 for none][quality u8]
+//Codec 0 turns the receiver down, the server closes the connection after it
+pub const WELCOME: u8 = 7;
+//Receiver to server, touch or pointer input, only used when the server allows it
+//[action u8][button u8][x u16][y u16], see PointerEvent
+pub const POINTER: u8 = 8;
+//Receiver to server, a key went down or up, only used when the server allows input
+//[1 down or 0 up u8][X keysym u32]
+pub const KEY: u8 = 9;
+//Receiver to server, text its keyboard committed, only used when the server allows input
+//[UTF-8 text]
+pub const TEXT: u8 = 10;
+//Both ways, a selection changed on one side, only used when the server synchronizes the clipboard
+//[selection u8, 0 CLIPBOARD or 1 PRIMARY][kind u8, 0 UTF-8 text or 1 PNG][data]
+pub const CLIPBOARD: u8 = 11;
+
+//Starts a message in output, the payload is appended after it and finish_message writes its length
+pub fn begin_message(kind: u8, output: &mut Vec<u8>) {
+    output.clear();
+    output.extend_from_slice(&[0, kind, 0, 0, 0, 0]);
+}
+
+pub fn finish_message(output: &mut [u8]) {
+    let len = (output.len() - HEADER_LEN) as u32;
+    output[2..HEADER_LEN].copy_from_slice(&len.to_be_bytes());
+}
+
+//Sent before the first frame so the receiver knows how to decode them
+pub fn codec_message(codec: Codec) -> Vec<u8> {
+    let mut output = Vec::new();
+    begin_message(CODEC, &mut output);
+    output.push(codec.id());
+    finish_message(&mut output);
+    output
+}
+
+//What a receiver told about itself in its hello
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hello {
+    pub version: u8,
+    pub screen: Option<Size>,
+    //In order of preference, the ids this server does not know are left out
+    pub codecs: Vec<Codec>,
+    //Ids of the codecs this server does not know
+    pub unknown_codecs: Vec<u8>,
+    pub max_frame_size: Option<usize>,
+}
+
+impl Hello {
+    fn parse(payload: &[u8]) -> Result<Self, String> {
+        if payload.len() < 10 || payload.len() < 10 + payload[9] as usize {
+            return Err(format!("The hello is too short, it has {} bytes", payload.len()));
+        }
+        let width = u16::from_be_bytes([payload[1], payload[2]]) as u32;
+        let height = u16::from_be_bytes([payload[3], payload[4]]) as u32;
+        let max_frame_size = u32::from_be_bytes(payload[5..9].try_into().unwrap()) as usize;
+        let ids = &payload[10..10 + payload[9] as usize];
+        Ok(Hello {
+            version: payload[0],
+            screen: Some(Size { width, height }).filter(|a| a.width > 0 && a.height > 0),
+            codecs: ids.iter().filter_map(|a| Codec::from_id(*a)).collect(),
+            unknown_codecs: ids.iter().filter(|a| Codec::from_id(**a).is_none()).copied().collect(),
+            max_frame_size: Some(max_frame_size).filter(|a| *a > 0),
+        })
+    }
+}
+
+//Answer to a hello with the options the receiver is going to get
+pub fn welcome_message(version: u8, options: &EncoderOptions) -> Vec<u8> {
+    let size = options.output.size.unwrap_or(Size { width: 0, height: 0 });
+    let mut output = Vec::new();
+    begin_message(WELCOME, &mut output);
+    output.extend_from_slice(&[version, options.codec.id()]);
+    output.extend_from_slice(&(size.width as u16).to_be_bytes());
+    output.extend_from_slice(&(size.height as u16).to_be_bytes());
+    output.extend_from_slice(&(options.max_frame_size.unwrap_or(0) as u32).to_be_bytes());
+    output.push(options.quality);
+    finish_message(&mut output);
+    output
+}
+
+//Welcome that turns the receiver down, it has nothing else set
+pub fn rejection_message(version: u8) -> Vec<u8> {
+    let mut output = Vec::new();
+    begin_message(WELCOME, &mut output);
+    output.extend_from_slice(&[version, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    finish_message(&mut output);
+    output
+}
+
+//X11 selections, the receivers that have only one clipboard use Clipboard
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Selection {
+    //What was copied explicitly
+    Clipboard,
+    //The text selected last, pasted with the middle button
+    Primary,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClipboardData {
+    Text(String),
+    Png(Vec<u8>),
+}
+
+//New content of a selection, sent by the side where it was copied
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClipboardContent {
+    pub selection: Selection,
+    pub data: ClipboardData,
+}
+
+impl ClipboardContent {
+    fn parse(payload: &[u8]) -> Result<Self, String> {
+        if payload.len() < 2 {
+            return Err(format!("The clipboard message is too short, it has {} bytes", payload.len()));
+        }
+        let selection = match payload[0] {
+            0 => Selection::Clipboard,
+            1 => Selection::Primary,
+            a => return Err(format!("Unknown selection {a}")),
+        };
+        let data = match payload[1] {
+            0 => match String::from_utf8(payload[2..].to_vec()) {
+                Ok(a) => ClipboardData::Text(a),
+                Err(_) => return Err("The clipboard text of the receiver is not UTF-8".to_string()),
+            },
+            1 => ClipboardData::Png(payload[2..].to_vec()),
+            a => return Err(format!("Unknown clipboard content {a}")),
+        };
+        Ok(ClipboardContent { selection, data })
+    }
+}
+
+//Tells the receiver what was copied on the desktop
+pub fn clipboard_message(content: &ClipboardContent) -> Vec<u8> {
+    let mut output = Vec::new();
+    begin_message(CLIPBOARD, &mut output);
+    output.push(match content.selection {
+        Selection::Clipboard => 0,
+        Selection::Primary => 1,
+    });
+    match &content.data {
+        ClipboardData::Text(a) => {
+            output.push(0);
+            output.extend_from_slice(a.as_bytes());
+        }
+        ClipboardData::Png(a) => {
+            output.push(1);
+            output.extend_from_slice(a);
+        }
+    }
+    finish_message(&mut output);
+    output
+}
+
+//Touch or pointer input of the receiver, the action byte is the index of the variant
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PointerEvent {
+    //Absolute position for touchscreens, x and y are fractions of the frame from 0 to 65535
+    MoveTo { x: u16, y: u16 },
+    //Relative move in desktop pixels for touchpads, x and y are signed and point where the receiver shows them
+    MoveBy { dx: i16, dy: i16 },
+    //X button numbers: 1 left, 2 middle, 3 right, 8 back and 9 forward
+    Press(u8),
+    Release(u8),
+    //Wheel steps, x and y are signed and positive is right and down
+    Scroll { dx: i16, dy: i16 },
+}
+
+impl PointerEvent {
+    fn parse(payload: &[u8]) -> Result<Self, String> {
+        let x = u16::from_be_bytes([payload[2], payload[3]]);
+        let y = u16::from_be_bytes([payload[4], payload[5]]);
+        let button = payload[1];
+        //4 to 7 are the wheel in X, scrolling has its own action
+        if matches!(payload[0], 2 | 3) && !matches!(button, 1..=3 | 8 | 9) {
+            return Err(format!("Button {button} can not be pressed by the receiver"));
+        }
+        match payload[0] {
+            0 => Ok(PointerEvent::MoveTo { x, y }),
+            1 => Ok(PointerEvent::MoveBy {
+                dx: x as i16,
+                dy: y as i16,
+            }),
+            2 => Ok(PointerEvent::Press(button)),
+            3 => Ok(PointerEvent::Release(button)),
+            4 => Ok(PointerEvent::Scroll {
+                dx: x as i16,
+                dy: y as i16,
+            }),
+            a => Err(format!("Unknown pointer action {a}")),
+        }
+    }
+}
+
+//Key of the receiver, for the keys that are not text like arrows, modifiers and shortcuts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEvent {
+    pub keysym: u32,
+    pub pressed: bool,
+}
+
+//Messages the receiver sends back
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    //ID of the last tile update the receiver has drawn
+    Ack(u32),
+    //Size the receiver wants the frames in, None for the captured size
+    Resolution(Option<Size>),
+    Orientation(Rotation),
+    Hello(Hello),
+    Pointer(PointerEvent),
+    Key(KeyEvent),
+    Text(String),
+    Clipboard(ClipboardContent),
+}
+
+//Splits the bytes coming from the receiver into messages, they can arrive in any chunks
+#[derive(Default)]
+pub struct MessageReader {
+    buffer: Vec<u8>,
+    //Bytes still to come of a message that is too big, they are dropped without being kept
+    skipping: usize,
+}
+
+impl MessageReader {
+    pub fn push(&mut self, data: &[u8]) {
+        let skip = self.skipping.min(data.len());
+        self.skipping -= skip;
+        self.buffer.extend_from_slice(&data[skip..]);
+    }
+
+    //Next complete message, an error means the message was not understood and was skipped
+    pub fn next_message(&mut self) -> Option<Result<ClientMessage, String>> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        if self.buffer[0] != 0 {
+            //Not the start of a message, drop bytes until one starts
+            let skip = self.buffer.iter().position(|a| *a == 0).unwrap_or(self.buffer.len());
+            self.buffer.drain(..skip);
+            return Some(Err(format!("Skipped {skip} bytes that are not a message")));
+        }
+        if self.buffer.len() < HEADER_LEN {
+            return None;
+        }
+        let kind = self.buffer[1];
+        let len = u32::from_be_bytes(self.buffer[2..HEADER_LEN].try_into().unwrap()) as usize;
+        if len > MAX_PAYLOAD {
+            //The next message starts after it
+            let skip = self.buffer.len().min(HEADER_LEN + len);
+            self.buffer.drain(..skip);
+            self.skipping = HEADER_LEN + len - skip;
+            return Some(Err(format!("Message {kind} with {len} bytes is too big, it is skipped")));
+        }
+        if self.buffer.len() < HEADER_LEN + len {
+            return None;
+        }
+        let message: Vec<u8> = self.buffer.drain(..HEADER_LEN + len).collect();
+        let payload = &message[HEADER_LEN..];
+        Some(match (kind, payload.len()) {
+            (ACK, 4) => Ok(ClientMessage::Ack(u32::from_be_bytes(payload.try_into().unwrap()))),
+            (RESOLUTION, 4) => {
+                let width = u16::from_be_bytes([payload[0], payload[1]]) as u32;
+                let height = u16::from_be_bytes([payload[2], payload[3]]) as u32;
+                Ok(ClientMessage::Resolution(
+                    Some(Size { width, height }).filter(|a| a.width > 0 && a.height > 0),
+                ))
+            }
+            (ORIENTATION, 1) => Ok(ClientMessage::Orientation(Rotation::from_quarters(payload[0]))),
+            (HELLO, _) => Hello::parse(payload).map(ClientMessage::Hello),
+            (POINTER, 6) => PointerEvent::parse(payload).map(ClientMessage::Pointer),
+            (KEY, 5) => Ok(ClientMessage::Key(KeyEvent {
+                keysym: u32::from_be_bytes(payload[1..5].try_into().unwrap()),
+                pressed: payload[0] != 0,
+            })),
+            (TEXT, _) => match String::from_utf8(payload.to_vec()) {
+                Ok(a) => Ok(ClientMessage::Text(a)),
+                Err(_) => Err("The text of the receiver is not UTF-8".to_string()),
+            },
+            (CLIPBOARD, _) => ClipboardContent::parse(payload).map(ClientMessage::Clipboard),
+            _ => Err(format!("Unknown message {kind} with {len} bytes")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bytes: &[u8]) -> Option<Result<ClientMessage, String>> {
+        let mut reader = MessageReader::default();
+        reader.push(bytes);
+        reader.next_message()
+    }
+
+    #[test]
+    fn clipboard_round_trip() {
+        for content in [
+            ClipboardContent {
+                selection: Selection::Clipboard,
+                data: ClipboardData::Text("héllo".to_string()),
+            },
+            ClipboardContent {
+                selection: Selection::Primary,
+                data: ClipboardData::Png(vec![0x89, b'P', b'N', b'G']),
+            },
+        ] {
+            assert_eq!(read(&clipboard_message(&content)), Some(Ok(ClientMessage::Clipboard(content))));
+        }
+    }
+
+    #[test]
+    fn rejection_has_no_codec() {
+        let output = rejection_message(1);
+        assert_eq!(&output[..HEADER_LEN + 2], [0, WELCOME, 0, 0, 0, 11, 1, 0]);
+        //Same length as a welcome
+        assert_eq!(output.len(), welcome_message(1, &EncoderOptions::default()).len());
+    }
+
+    #[test]
+    fn too_big_is_skipped() {
+        let len = MAX_PAYLOAD + 1;
+        let mut reader = MessageReader::default();
+        reader.push(&[0, CLIPBOARD]);
+        reader.push(&(len as u32).to_be_bytes());
+        //Zeros inside the payload must not be read as messages
+        reader.push(&[0; 100]);
+        assert!(matches!(reader.next_message(), Some(Err(_))));
+        assert_eq!(reader.next_message(), None);
+        reader.push(&vec![0; len - 101]);
+        assert_eq!(reader.next_message(), None);
+        //The last byte of the payload and the next message come together
+        reader.push(&[0, 0, ACK, 0, 0, 0, 4, 0, 0, 0, 7]);
+        assert_eq!(reader.next_message(), Some(Ok(ClientMessage::Ack(7))));
+        assert_eq!(reader.next_message(), None);
+    }
+
+    #[test]
+    fn input_messages() {
+        assert_eq!(
+            read(&[0, POINTER, 0, 0, 0, 6, 1, 0, 0xff, 0xf6, 0, 5]),
+            Some(Ok(ClientMessage::Pointer(PointerEvent::MoveBy { dx: -10, dy: 5 })))
+        );
+        //Buttons 4 to 7 are the wheel
+        assert!(matches!(read(&[0, POINTER, 0, 0, 0, 6, 2, 4, 0, 0, 0, 0]), Some(Err(_))));
+        assert_eq!(
+            read(&[0, KEY, 0, 0, 0, 5, 1, 0, 0, 0xff, 0x0d]),
+            Some(Ok(ClientMessage::Key(KeyEvent {
+                keysym: 0xff0d,
+                pressed: true,
+            })))
+        );
+        assert_eq!(
+            read(&[0, TEXT, 0, 0, 0, 2, b'o', b'k']),
+            Some(Ok(ClientMessage::Text("ok".to_string())))
+        );
+    }
+}
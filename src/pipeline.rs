@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::adaptive::QualityController;
+use crate::budget::FrameBudget;
+use crate::capture::{CaptureOptions, Rect};
+use crate::clipboard::Clipboard;
+use crate::encoder::{new_encoder, Codec, Encoder, EncoderOptions};
+use crate::error::{GabinatorError, Logger, LoggerLevel};
+use crate::input::{DesktopPointer, InputInjector};
+use crate::message::{clipboard_message, ClientMessage, PointerEvent};
+use crate::protocol::{timestamp_now, FrameHeader, FLAG_KEYFRAME, HEADER_LEN, VERSION};
+use crate::scale::{Scaler, Size};
+use crate::source::{open_source, FrameSource, SourceKind};
+use crate::tiles::TileEncoder;
+use crate::transform::{Rotation, Transform, Transformer};
+
+//What the transports send, frames are their header followed by the data and messages go as they are
+pub enum Packet {
+    Frame(FrameHeader, Vec<u8>),
+    Message(Vec<u8>),
+}
+
+impl Packet {
+    //Bytes to write before the data
+    pub fn header(&self) -> Option<[u8; HEADER_LEN]> {
+        match self {
+            Packet::Frame(header, _) => Some(header.encode()),
+            Packet::Message(_) => None,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Packet::Frame(_, a) | Packet::Message(a) => a,
+        }
+    }
+
+    //Header and data together, as they go on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = self.header().map(|a| a.to_vec()).unwrap_or_default();
+        output.extend_from_slice(self.data());
+        output
+    }
+}
+
+//Frame source and encoder, everything the transports need to get the next frame to send
+pub struct Pipeline {
+    source: Box<dyn FrameSource>,
+    encoder: Box<dyn Encoder>,
+    //Only with delta encoding, the encoder is then used for every tile
+    tiles: Option<TileEncoder>,
+    //Only with a quality target
+    controller: Option<QualityController>,
+    //Only with a maximum frame size
+    budget: Option<FrameBudget>,
+    transformer: Transformer,
+    scaler: Scaler,
+    //Only when the receiver is allowed to control the pointer
+    input: Option<InputInjector>,
+    //Only when the clipboard is synchronized
+    clipboard: Option<Clipboard>,
+    //Size of the last captured frame and the transform it got, to map the pointer back to the desktop
+    last_frame: Option<(Size, Transform)>,
+    //Time spent encoding the last frame, the transport adds the time it took to send it
+    encode_time: Duration,
+    //ID of the next frame, in the frame header
+    next_id: u32,
+    //Protocol version agreed with the receiver, in the frame header
+    version: u8,
+    //Encoder options as the stages use them, what the receiver is told in the welcome
+    applied: EncoderOptions,
+    config: HashMap<String, String>,
+}
+
+//Everything after the source, they depend on the encoder options
+struct Stages {
+    encoder: Box<dyn Encoder>,
+    tiles: Option<TileEncoder>,
+    controller: Option<QualityController>,
+    budget: Option<FrameBudget>,
+    transformer: Transformer,
+    scaler: Scaler,
+    applied: EncoderOptions,
+}
+
+//Which of the optional stages the encoder options get with the codec of the encoder
+struct StagePlan {
+    tiles: Option<u16>,
+    max_frame_size: Option<usize>,
+    //The encoder options without what is left out
+    applied: EncoderOptions,
+    //Why something was left out
+    warnings: Vec<String>,
+}
+
+impl StagePlan {
+    fn new(encoder_options: &EncoderOptions, codec: Codec) -> Self {
+        let mut warnings = Vec::new();
+        //Video frames depend on the ones before them, they can not be split in independent tiles
+        let tiles = match (encoder_options.tiles, codec) {
+            (Some(_), Codec::H264) => {
+                warnings.push("Tiles are not used with H.264, whole frames are sent".to_string());
+                None
+            }
+            (a, _) => a,
+        };
+        //The budget decides the quality and scale of every frame by itself
+        let max_frame_size = match (encoder_options.max_frame_size, codec) {
+            (Some(_), Codec::H264) => {
+                warnings.push("The maximum frame size is not used with H.264, the bitrate limits it".to_string());
+                None
+            }
+            (a, _) => a,
+        };
+        if max_frame_size.is_some() && (tiles.is_some() || encoder_options.adaptive.target.is_some()) {
+            warnings.push("Tiles and the quality target are not used with a maximum frame size".to_string());
+        }
+        let tiles = tiles.filter(|_| max_frame_size.is_none());
+        StagePlan {
+            tiles,
+            max_frame_size,
+            applied: EncoderOptions {
+                codec,
+                tiles,
+                max_frame_size,
+                ..encoder_options.clone()
+            },
+            warnings,
+        }
+    }
+}
+
+impl Stages {
+    fn new(encoder_options: &EncoderOptions, config: &HashMap<String, String>) -> Result<Self, GabinatorError> {
+        let mut encoder = match new_encoder(encoder_options) {
+            Ok(a) => a,
+            Err(a) => {
+                return Err(GabinatorError::newCapture(
+                    format!("Not able to start the {:?} encoder: {a}", encoder_options.codec),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                ))
+            }
+        };
+        let plan = StagePlan::new(encoder_options, encoder.codec());
+        for warning in plan.warnings {
+            GabinatorError::newCapture(warning, LoggerLevel::Warning, Some(config.clone()));
+        }
+        let tiles = plan.tiles.map(TileEncoder::new);
+        let budget = plan
+            .max_frame_size
+            .map(|a| FrameBudget::new(a, &encoder_options.adaptive, encoder_options.quality));
+        let controller = QualityController::new(encoder_options.adaptive, encoder_options.quality)
+            .filter(|_| budget.is_none());
+        if let Some(controller) = &controller {
+            if let Err(a) = encoder.set_quality(controller.quality()) {
+                return Err(GabinatorError::newCapture(
+                    format!("Not able to set the quality: {a}"),
+                    LoggerLevel::Error,
+                    Some(config.clone()),
+                ));
+            }
+        }
+        Ok(Stages {
+            applied: plan.applied,
+            encoder,
+            tiles,
+            controller,
+            budget,
+            transformer: Transformer::new(encoder_options.transform),
+            scaler: Scaler::new(encoder_options.output),
+        })
+    }
+}
+
+impl Pipeline {
+    pub fn new(encoder_options: &EncoderOptions, options: CaptureOptions) -> Result<Self, GabinatorError> {
+        let config = Logger::get_config_content();
+        let input = match (options.input, &options.source) {
+            (false, _) => None,
+            (true, SourceKind::Screen) => InputInjector::new(&config).ok(),
+            (true, _) => {
+                GabinatorError::newCapture(
+                    "The receiver can only control the pointer with the screen source, its input is ignored",
+                    LoggerLevel::Warning,
+                    Some(config.clone()),
+                );
+                None
+            }
+        };
+        //The error was already logged, the frames are sent anyway
+        let clipboard = match options.clipboard {
+            true => Clipboard::new(options.clipboard_images, &config).ok(),
+            false => None,
+        };
+        let source = open_source(options)?;
+        let stages = Stages::new(encoder_options, &config)?;
+        Ok(Pipeline {
+            source,
+            encoder: stages.encoder,
+            tiles: stages.tiles,
+            controller: stages.controller,
+            budget: stages.budget,
+            transformer: stages.transformer,
+            scaler: stages.scaler,
+            input,
+            clipboard,
+            last_frame: None,
+            encode_time: Duration::ZERO,
+            next_id: 0,
+            version: VERSION,
+            applied: stages.applied,
+            config,
+        })
+    }
+
+    //Uses other encoder options from the next frame on, the source stays open
+    pub fn reconfigure(&mut self, encoder_options: &EncoderOptions) -> Result<(), GabinatorError> {
+        let stages = Stages::new(encoder_options, &self.config)?;
+        self.encoder = stages.encoder;
+        self.tiles = stages.tiles;
+        self.controller = stages.controller;
+        self.budget = stages.budget;
+        self.transformer = stages.transformer;
+        self.scaler = stages.scaler;
+        self.applied = stages.applied;
+        self.source.invalidate();
+        Ok(())
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.encoder.codec()
+    }
+
+    //Encoder options given to the last reconfigure, with the codec and maximum frame size really used
+    pub fn applied_options(&self) -> &EncoderOptions {
+        &self.applied
+    }
+
+    //Protocol version written in the frame headers from now on
+    pub fn set_version(&mut self, version: u8) {
+        self.version = version;
+    }
+
+    //Next packet to send, None if nothing changed since the last one
+    pub fn next_frame(&mut self) -> Result<Option<Packet>, GabinatorError> {
+        //What was copied on the desktop goes before the next frame
+        if let Some(a) = self.clipboard.as_ref().and_then(|a| a.changed()) {
+            return Ok(Some(Packet::Message(clipboard_message(&a))));
+        }
+        let frame = match self.source.next_frame()? {
+            Some(a) => a,
+            None => return Ok(None),
+        };
+        let timestamp = timestamp_now();
+        let start = Instant::now();
+        let (version, codec, frame_id) = (self.version, self.encoder.codec(), self.next_id);
+        let packet = |data: Vec<u8>, width: u32, height: u32, keyframe: bool| {
+            let header = FrameHeader {
+                version,
+                codec,
+                flags: if keyframe { FLAG_KEYFRAME } else { 0 },
+                frame_id,
+                timestamp,
+                width: width as u16,
+                height: height as u16,
+                payload_length: data.len() as u32,
+            };
+            Packet::Frame(header, data)
+        };
+        let captured = Size {
+            width: frame.width,
+            height: frame.height,
+        };
+        self.last_frame = Some((captured, self.transformer.transform()));
+        let frame = self.transformer.apply(frame);
+        let result = match &mut self.budget {
+            Some(budget) => match budget.encode(&frame, self.encoder.as_mut(), &mut self.scaler) {
+                Ok(None) => {
+                    GabinatorError::newCapture(
+                        "The frame does not fit in the maximum frame size, it is skipped".to_string(),
+                        LoggerLevel::Warning,
+                        Some(self.config.clone()),
+                    );
+                    Ok(None)
+                }
+                a => a.map(|a| a.map(|(data, size)| packet(data, size.width, size.height, self.encoder.keyframe()))),
+            },
+            None => {
+                let scale = self.controller.as_ref().map(|a| a.scale()).unwrap_or(1.0);
+                let frame = self.scaler.resize(frame, scale);
+                match &mut self.tiles {
+                    Some(tiles) => tiles
+                        .encode(&frame, self.encoder.as_mut())
+                        .map(|a| a.map(Packet::Message)),
+                    None => self
+                        .encoder
+                        .encode(&frame)
+                        .map(|a| Some(packet(a, frame.width, frame.height, self.encoder.keyframe()))),
+                }
+            }
+        };
+        self.encode_time = start.elapsed();
+        if let Ok(Some(Packet::Frame(..))) = &result {
+            self.next_id = self.next_id.wrapping_add(1);
+        }
+        match result {
+            Ok(a) => Ok(a),
+            Err(a) => Err(GabinatorError::newCapture(
+                format!("Error compressing the frame: {a}"),
+                LoggerLevel::Error,
+                Some(self.config.clone()),
+            )),
+        }
+    }
+
+    //Gives a packet returned by next_frame back once it was sent, so its buffer is reused
+    pub fn recycle(&mut self, packet: Packet) {
+        match (packet, &mut self.tiles) {
+            (Packet::Message(a), Some(tiles)) => tiles.recycle(a),
+            (Packet::Frame(_, a), _) | (Packet::Message(a), None) => self.encoder.recycle(a),
+        }
+    }
+
+    //Tells the quality controller a packet of len bytes was sent in send_time
+    pub fn report_sent(&mut self, len: usize, send_time: Duration) {
+        let controller = match &mut self.controller {
+            Some(a) => a,
+            None => return,
+        };
+        if !controller.record(len, self.encode_time + send_time) {
+            return;
+        }
+        Logger::log(
+            format!(
+                "Quality changed to {} at {:.0}% of the resolution",
+                controller.quality(),
+                controller.scale() * 100.0
+            ),
+            LoggerLevel::Debug,
+            Some(self.config.clone()),
+        );
+        if let Err(a) = self.encoder.set_quality(controller.quality()) {
+            GabinatorError::newCapture(
+                format!("Not able to change the quality: {a}"),
+                LoggerLevel::Warning,
+                Some(self.config.clone()),
+            );
+        }
+    }
+
+    //Applies a message sent by the receiver
+    pub fn handle_message(&mut self, message: ClientMessage) {
+        match message {
+            ClientMessage::Ack(id) => {
+                if let Some(tiles) = &mut self.tiles {
+                    tiles.acknowledge(id);
+                }
+            }
+            ClientMessage::Resolution(a) => {
+                let message = match a {
+                    Some(b) => format!("The receiver asked for {}x{} frames", b.width, b.height),
+                    None => "The receiver asked for frames of the captured size".to_string(),
+                };
+                Logger::log(message, LoggerLevel::Info, Some(self.config.clone()));
+                self.scaler.set_size(a);
+            }
+            ClientMessage::Orientation(a) => {
+                Logger::log(
+                    format!("The receiver changed its orientation, the frames are rotated {} degrees", a.degrees()),
+                    LoggerLevel::Info,
+                    Some(self.config.clone()),
+                );
+                self.transformer.set_rotation(a);
+            }
+            //Only the transports read it, when the receiver connects
+            ClientMessage::Hello(_) => Logger::log(
+                "The receiver sent a hello after connecting, it is ignored".to_string(),
+                LoggerLevel::Warning,
+                Some(self.config.clone()),
+            ),
+            ClientMessage::Pointer(a) => self.inject(a),
+            ClientMessage::Key(a) => self.inject_keys(|input| input.key(a.keysym, a.pressed)),
+            ClientMessage::Text(a) => self.inject_keys(|input| input.type_text(&a)),
+            ClientMessage::Clipboard(a) => {
+                if let Some(clipboard) = &self.clipboard {
+                    clipboard.set(a);
+                }
+            }
+        }
+    }
+
+    //Injects keys or pointer events for the receiver, nothing happens when input is off
+    fn inject_keys(&mut self, inject: impl FnOnce(&mut InputInjector) -> Result<(), String>) {
+        let result = match &mut self.input {
+            Some(a) => inject(a),
+            None => return,
+        };
+        if let Err(a) = result {
+            GabinatorError::newCapture(a, LoggerLevel::Warning, Some(self.config.clone()));
+        }
+    }
+
+    //Moves the pointer or clicks where the receiver asked, positions are mapped from the last frame to the desktop
+    fn inject(&mut self, event: PointerEvent) {
+        //Input is off, the receiver only watches
+        if self.input.is_none() {
+            return;
+        }
+        let transform = self.transformer.transform();
+        let event = match event {
+            PointerEvent::MoveTo { x, y } => {
+                let fraction = |a: u16| a as f32 / u16::MAX as f32;
+                let point = self.scaler.frame_point(fraction(x), fraction(y));
+                let (point, (captured, transform), origin) =
+                    match (point, self.last_frame, self.source.desktop_origin()) {
+                        (Some(a), Some(b), Some(c)) => (a, b, c),
+                        //Nothing was sent yet, the receiver can not point at anything
+                        _ => return,
+                    };
+                let (width, height) = (captured.width as usize, captured.height as usize);
+                //The rotation can change between the frame and the event, the point stays on the frame anyway
+                let (x, y) = match transform.rotation {
+                    Rotation::R90 | Rotation::R270 => ((point.0 as usize).min(height - 1), (point.1 as usize).min(width - 1)),
+                    _ => ((point.0 as usize).min(width - 1), (point.1 as usize).min(height - 1)),
+                };
+                let (x, y) = transform.source_pixel(x, y, width, height);
+                DesktopPointer::MoveTo(origin.0 + x as i32, origin.1 + y as i32)
+            }
+            PointerEvent::MoveBy { dx, dy } => {
+                let (dx, dy) = transform.source_vector(dx as i32, dy as i32);
+                DesktopPointer::MoveBy(dx, dy)
+            }
+            PointerEvent::Press(a) => DesktopPointer::Press(a),
+            PointerEvent::Release(a) => DesktopPointer::Release(a),
+            PointerEvent::Scroll { dx, dy } => {
+                let (dx, dy) = transform.source_vector(dx as i32, dy as i32);
+                DesktopPointer::Scroll(dx, dy)
+            }
+        };
+        self.inject_keys(|input| input.inject(event));
+    }
+
+    //The receiver is gone, what it held down is released so the desktop is not left with stuck keys or buttons
+    pub fn client_disconnected(&mut self) {
+        self.inject_keys(|input| input.release_all());
+    }
+
+    //Everything is sent again, for a receiver that has nothing yet
+    pub fn invalidate(&mut self) {
+        self.source.invalidate();
+        self.encoder.request_keyframe();
+        if let Some(controller) = &mut self.controller {
+            controller.restart();
+        }
+        if let Some(tiles) = &mut self.tiles {
+            tiles.reset();
+        }
+    }
+
+    pub fn dirty_rects(&self) -> &[Rect] {
+        self.source.dirty_rects()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applied_options_drop_the_unused_budget() {
+        let options = EncoderOptions {
+            max_frame_size: Some(50_000),
+            ..Default::default()
+        };
+        let plan = StagePlan::new(&options, Codec::Jpeg);
+        assert_eq!(plan.applied.max_frame_size, Some(50_000));
+        assert!(plan.warnings.is_empty());
+
+        //H.264 is limited by its bitrate, the receiver must not be told about a maximum frame size
+        let plan = StagePlan::new(&options, Codec::H264);
+        assert_eq!(plan.applied.codec, Codec::H264);
+        assert_eq!(plan.max_frame_size, None);
+        assert_eq!(plan.applied.max_frame_size, None);
+        assert_eq!(plan.warnings.len(), 1);
+    }
+
+    #[test]
+    fn budget_replaces_the_tiles() {
+        let options = EncoderOptions {
+            tiles: Some(64),
+            max_frame_size: Some(50_000),
+            ..Default::default()
+        };
+        let plan = StagePlan::new(&options, Codec::Jpeg);
+        assert_eq!((plan.tiles, plan.applied.tiles), (None, None));
+        assert_eq!(plan.warnings.len(), 1);
+
+        //The applied codec is the one of the encoder
+        let options = EncoderOptions {
+            max_frame_size: None,
+            ..options
+        };
+        let plan = StagePlan::new(&options, Codec::Png);
+        assert_eq!((plan.tiles, plan.applied.codec), (Some(64), Codec::Png));
+        assert!(plan.warnings.is_empty());
+    }
+}
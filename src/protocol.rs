@@ -0,0 +1,157 @@
+//Wire format of the frames, the same for every transport and every platform.
+//
+//Each frame is a header followed by payload_length bytes of encoded image, all numbers are big endian:
+//
+//  offset  size  field
+//  0       4     magic, "GBNR"
+//  4       1     version, the one agreed with the receiver, at most VERSION
+//  5       1     codec, the id of Codec
+//  6       2     flags, FLAG_*
+//  8       4     frame id, counts up by one per frame and wraps
+//  12      8     capture timestamp, microseconds since the Unix epoch
+//  20      2     width of the encoded image
+//  22      2     height of the encoded image
+//  24      4     payload length
+//
+//Control messages (see message.rs) share the stream, they start with a 0 byte and a frame never does
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::encoder::Codec;
+
+pub const MAGIC: [u8; 4] = *b"GBNR";
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 28;
+
+//The frame can be decoded without the ones before it
+pub const FLAG_KEYFRAME: u16 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameHeader {
+    pub version: u8,
+    pub codec: Codec,
+    pub flags: u16,
+    pub frame_id: u32,
+    pub timestamp: u64,
+    pub width: u16,
+    pub height: u16,
+    pub payload_length: u32,
+}
+
+impl FrameHeader {
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut output = [0u8; HEADER_LEN];
+        output[0..4].copy_from_slice(&MAGIC);
+        output[4] = self.version;
+        output[5] = self.codec.id();
+        output[6..8].copy_from_slice(&self.flags.to_be_bytes());
+        output[8..12].copy_from_slice(&self.frame_id.to_be_bytes());
+        output[12..20].copy_from_slice(&self.timestamp.to_be_bytes());
+        output[20..22].copy_from_slice(&self.width.to_be_bytes());
+        output[22..24].copy_from_slice(&self.height.to_be_bytes());
+        output[24..28].copy_from_slice(&self.payload_length.to_be_bytes());
+        output
+    }
+
+    //Reads the header at the start of input, the payload is not needed
+    pub fn decode(input: &[u8]) -> Result<Self, String> {
+        if input.len() < HEADER_LEN {
+            return Err(format!("The header needs {HEADER_LEN} bytes, there are {}", input.len()));
+        }
+        if input[0..4] != MAGIC {
+            return Err(format!("Not a frame, it starts with {:02x?}", &input[0..4]));
+        }
+        if input[4] == 0 || input[4] > VERSION {
+            return Err(format!("Version {} of the protocol is not supported, only up to {VERSION}", input[4]));
+        }
+        let codec = match Codec::from_id(input[5]) {
+            Some(a) => a,
+            None => return Err(format!("Unknown codec {}", input[5])),
+        };
+        Ok(FrameHeader {
+            version: input[4],
+            codec,
+            flags: u16::from_be_bytes([input[6], input[7]]),
+            frame_id: u32::from_be_bytes(input[8..12].try_into().unwrap()),
+            timestamp: u64::from_be_bytes(input[12..20].try_into().unwrap()),
+            width: u16::from_be_bytes([input[20], input[21]]),
+            height: u16::from_be_bytes([input[22], input[23]]),
+            payload_length: u32::from_be_bytes(input[24..28].try_into().unwrap()),
+        })
+    }
+}
+
+//Capture timestamp for a frame taken now
+pub fn timestamp_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|a| a.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> FrameHeader {
+        FrameHeader {
+            version: VERSION,
+            codec: Codec::Jpeg,
+            flags: FLAG_KEYFRAME,
+            frame_id: 0x01020304,
+            timestamp: 1_700_000_000_123_456,
+            width: 1920,
+            height: 1080,
+            payload_length: 123_456,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let header = header();
+        assert_eq!(FrameHeader::decode(&header.encode()), Ok(header));
+    }
+
+    #[test]
+    fn round_trip_every_codec() {
+        for id in 0..=u8::MAX {
+            if let Some(codec) = Codec::from_id(id) {
+                let header = FrameHeader { codec, ..header() };
+                assert_eq!(FrameHeader::decode(&header.encode()), Ok(header));
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_layout() {
+        let bytes = header().encode();
+        assert_eq!(&bytes[0..8], b"GBNR\x01\x01\x00\x01");
+        assert_eq!(&bytes[8..12], [1, 2, 3, 4]);
+        assert_eq!(&bytes[20..28], [0x07, 0x80, 0x04, 0x38, 0x00, 0x01, 0xe2, 0x40]);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let bytes = header().encode();
+        assert!(FrameHeader::decode(&bytes[..HEADER_LEN - 1]).is_err());
+        let mut wrong = bytes;
+        wrong[0] = 0;
+        assert!(FrameHeader::decode(&wrong).is_err());
+        let mut wrong = bytes;
+        wrong[4] = VERSION + 1;
+        assert!(FrameHeader::decode(&wrong).is_err());
+        let mut wrong = bytes;
+        wrong[4] = 0;
+        assert!(FrameHeader::decode(&wrong).is_err());
+        let mut wrong = bytes;
+        wrong[5] = 0;
+        assert!(FrameHeader::decode(&wrong).is_err());
+    }
+
+    #[test]
+    fn trailing_payload_is_ignored() {
+        let mut bytes = header().encode().to_vec();
+        bytes.extend_from_slice(&[0xff; 16]);
+        assert_eq!(FrameHeader::decode(&bytes), Ok(header()));
+    }
+}
@@ -0,0 +1,203 @@
+use std::str::FromStr;
+
+use crate::encoder::{BufferPool, Codec, Encoder};
+use crate::frame::Frame;
+
+//How the gray levels that do not exist in 4 and 1 bit are approximated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dither {
+    //Error diffusion, the best looking one but the pattern changes between frames
+    FloydSteinberg,
+    //4x4 Bayer matrix, stable between frames so e-ink panels redraw less
+    Ordered,
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "floyd" | "floyd-steinberg" => Ok(Dither::FloydSteinberg),
+            "ordered" | "bayer" => Ok(Dither::Ordered),
+            _ => Err(format!("Not a valid dither {s}, use floyd or ordered")),
+        }
+    }
+}
+
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+//Uncompressed frames for receivers that can not decode images.
+//They start with [width u16][height u16] big endian, then the rows, each one padded to a whole byte:
+//Gray4 has two pixels per byte with the first one in the high nibble, Mono has eight pixels per byte
+//with the first one in the highest bit and 1 for white, Rgb565 has one big endian u16 per pixel
+pub struct RawEncoder {
+    codec: Codec,
+    dither: Dither,
+    //Luminance of the frame and the Floyd-Steinberg errors of the current and the next row
+    gray: Vec<u8>,
+    errors: [Vec<i32>; 2],
+    pool: BufferPool,
+}
+
+impl RawEncoder {
+    pub fn new(codec: Codec, dither: Dither) -> Self {
+        RawEncoder {
+            codec,
+            dither,
+            gray: Vec::new(),
+            errors: [Vec::new(), Vec::new()],
+            pool: BufferPool::default(),
+        }
+    }
+
+    //Gray levels of the frame, 0 to levels - 1, written over the luminance
+    fn quantize(&mut self, width: usize, height: usize, levels: i32) {
+        let step = 255 / (levels - 1);
+        match self.dither {
+            Dither::Ordered => {
+                for (y, row) in self.gray.chunks_exact_mut(width).enumerate() {
+                    for (x, value) in row.iter_mut().enumerate() {
+                        let threshold = (2 * BAYER[y % 4][x % 4] as i32 + 1) * 255 / 32;
+                        *value = ((*value as i32 * (levels - 1) + threshold) / 255).min(levels - 1) as u8;
+                    }
+                }
+            }
+            Dither::FloydSteinberg => {
+                //One extra value on each side, so the errors can go past the edges
+                for a in &mut self.errors {
+                    a.clear();
+                    a.resize(width + 2, 0);
+                }
+                for y in 0..height {
+                    let [current, next] = &mut self.errors;
+                    next.fill(0);
+                    for x in 0..width {
+                        let value = self.gray[y * width + x] as i32 + current[x + 1] / 16;
+                        let level = ((value + step / 2) / step).clamp(0, levels - 1);
+                        let error = value - level * step;
+                        current[x + 2] += error * 7;
+                        next[x] += error * 3;
+                        next[x + 1] += error * 5;
+                        next[x + 2] += error;
+                        self.gray[y * width + x] = level as u8;
+                    }
+                    self.errors.swap(0, 1);
+                }
+            }
+        }
+    }
+}
+
+impl Encoder for RawEncoder {
+    fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    fn encode(&mut self, frame: &Frame) -> Result<Vec<u8>, String> {
+        let (width, height) = (frame.width as usize, frame.height as usize);
+        if width > u16::MAX as usize || height > u16::MAX as usize {
+            return Err(format!("The frame is too big for a raw frame: {width}x{height}"));
+        }
+        let (red, green, blue) = frame.format.channels();
+        let bytes_per_pixel = frame.format.bytes_per_pixel();
+        let pixels = |y: usize| {
+            let start = y * frame.pitch;
+            frame.data[start..start + width * bytes_per_pixel].chunks_exact(bytes_per_pixel)
+        };
+
+        let mut buffer = self.pool.take(0);
+        buffer.extend_from_slice(&(width as u16).to_be_bytes());
+        buffer.extend_from_slice(&(height as u16).to_be_bytes());
+        if self.codec == Codec::Rgb565 {
+            for y in 0..height {
+                for pixel in pixels(y) {
+                    let (r, g, b) = (pixel[red] as u16, pixel[green] as u16, pixel[blue] as u16);
+                    buffer.extend_from_slice(&((r >> 3) << 11 | (g >> 2) << 5 | b >> 3).to_be_bytes());
+                }
+            }
+            return Ok(buffer);
+        }
+
+        //BT.601 luminance
+        self.gray.clear();
+        for y in 0..height {
+            for pixel in pixels(y) {
+                let (r, g, b) = (pixel[red] as u32, pixel[green] as u32, pixel[blue] as u32);
+                self.gray.push(((77 * r + 150 * g + 29 * b) >> 8) as u8);
+            }
+        }
+        let bits = match self.codec {
+            Codec::Gray4 => 4,
+            Codec::Mono => 1,
+            codec => {
+                self.pool.give_back(buffer);
+                return Err(format!("{codec:?} is not a raw codec"));
+            }
+        };
+        self.quantize(width, height, 1 << bits);
+        let per_byte = 8 / bits;
+        for row in self.gray.chunks_exact(width) {
+            for chunk in row.chunks(per_byte) {
+                let mut byte = 0u8;
+                for (index, level) in chunk.iter().enumerate() {
+                    byte |= level << (8 - bits * (index + 1));
+                }
+                buffer.push(byte);
+            }
+        }
+        Ok(buffer)
+    }
+
+    fn recycle(&mut self, buffer: Vec<u8>) {
+        self.pool.give_back(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::PixelFormat;
+
+    fn encode(codec: Codec, dither: Dither, data: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let frame = Frame {
+            data,
+            width,
+            height,
+            pitch: width as usize * 3,
+            format: PixelFormat::Rgb,
+        };
+        RawEncoder::new(codec, dither).encode(&frame).unwrap()
+    }
+
+    #[test]
+    fn rgb565_big_endian() {
+        let data = [0xff, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0xff];
+        let output = encode(Codec::Rgb565, Dither::Ordered, &data, 3, 1);
+        assert_eq!(output, [0, 3, 0, 1, 0xf8, 0x00, 0x07, 0xe0, 0x00, 0x1f]);
+    }
+
+    #[test]
+    fn gray4_high_nibble_first() {
+        //White, black and white, the last byte is padded with black
+        let data = [0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff];
+        let output = encode(Codec::Gray4, Dither::FloydSteinberg, &data, 3, 1);
+        assert_eq!(output, [0, 3, 0, 1, 0xf0, 0xf0]);
+    }
+
+    #[test]
+    fn mono_rows_padded() {
+        //9 white pixels per row need 2 bytes
+        let data = [0xff; 9 * 2 * 3];
+        let output = encode(Codec::Mono, Dither::Ordered, &data, 9, 2);
+        assert_eq!(output, [0, 9, 0, 2, 0xff, 0x80, 0xff, 0x80]);
+    }
+
+    #[test]
+    fn floyd_steinberg_keeps_the_average() {
+        //Mid gray becomes about half white pixels
+        let data = [0x80; 16 * 16 * 3];
+        let output = encode(Codec::Mono, Dither::FloydSteinberg, &data, 16, 16);
+        let white: u32 = output[4..].iter().map(|a| a.count_ones()).sum();
+        assert!((120..=136).contains(&white), "{white} white pixels");
+    }
+}
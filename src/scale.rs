@@ -0,0 +1,473 @@
+use std::str::FromStr;
+
+use image::imageops::{self, FilterType};
+use image::{ImageBuffer, Rgb};
+
+use crate::encoder::pack_rgb;
+use crate::frame::{Frame, PixelFormat};
+
+//How the frame is made to match the output size when their aspect ratios differ
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScaleMode {
+    //The whole frame is shown, the rest is filled with the letterbox color
+    Fit,
+    //The output is covered, the sides of the frame that do not fit are cut
+    Fill,
+    //The frame is deformed to the output size
+    Stretch,
+}
+
+impl FromStr for ScaleMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fit" => Ok(ScaleMode::Fit),
+            "fill" => Ok(ScaleMode::Fill),
+            "stretch" => Ok(ScaleMode::Stretch),
+            _ => Err(format!("Not a valid scale mode {s}, use fit, fill or stretch")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    //Sharpest, and the slowest
+    Lanczos,
+}
+
+impl Filter {
+    fn image(self) -> FilterType {
+        match self {
+            Filter::Nearest => FilterType::Nearest,
+            Filter::Bilinear => FilterType::Triangle,
+            Filter::Lanczos => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nearest" => Ok(Filter::Nearest),
+            "bilinear" => Ok(Filter::Bilinear),
+            "lanczos" => Ok(Filter::Lanczos),
+            _ => Err(format!("Not a valid filter {s}, use nearest, bilinear or lanczos")),
+        }
+    }
+}
+
+//Size of the frames sent to the receiver
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = match s.split_once(['x', 'X']) {
+            Some(a) => a,
+            None => return Err(format!("Expected widthxheight but got {s}")),
+        };
+        let parse_error = |a: std::num::ParseIntError| format!("Not a valid size {s}: {a}");
+        let size = Size {
+            width: width.trim().parse().map_err(parse_error)?,
+            height: height.trim().parse().map_err(parse_error)?,
+        };
+        if size.width == 0 || size.height == 0 {
+            return Err(format!("The size {s} is empty"));
+        }
+        Ok(size)
+    }
+}
+
+//RGB color, written as RRGGBB in hexadecimal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color(pub [u8; 3]);
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim().trim_start_matches('#');
+        match u32::from_str_radix(hex, 16) {
+            //from_str_radix takes a sign too
+            Ok(a) if hex.len() == 6 && hex.chars().all(|a| a.is_ascii_hexdigit()) => {
+                let [_, red, green, blue] = a.to_be_bytes();
+                Ok(Color([red, green, blue]))
+            }
+            _ => Err(format!("Not a valid color {s}, use RRGGBB")),
+        }
+    }
+}
+
+//How the frames are resized before they are encoded
+#[derive(Clone, Copy, Debug)]
+pub struct ScaleOptions {
+    //None keeps the size of the captured frames
+    pub size: Option<Size>,
+    pub mode: ScaleMode,
+    pub filter: Filter,
+    pub letterbox: Color,
+}
+
+impl Default for ScaleOptions {
+    fn default() -> Self {
+        ScaleOptions {
+            size: None,
+            mode: ScaleMode::Fit,
+            filter: Filter::Bilinear,
+            letterbox: Color([0, 0, 0]),
+        }
+    }
+}
+
+//Rectangle in pixels of a frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Area {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Area {
+    fn whole(width: u32, height: u32) -> Self {
+        Area {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+}
+
+//Where the last resized frame went, to find the frame pixel under a point of the output
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Placement {
+    //Part of the frame that was used, fill cuts the sides
+    crop: Area,
+    //Part of the output it was drawn in, fit leaves the letterbox around it
+    content: Area,
+    output: Size,
+}
+
+//Resizes frames to the output size, the resized frames are packed RGB
+pub struct Scaler {
+    options: ScaleOptions,
+    placement: Option<Placement>,
+    rgb: Vec<u8>,
+    //Last resized frame, fit draws it on the canvas
+    inner: Vec<u8>,
+    canvas: Vec<u8>,
+    //Placement the letterbox of the canvas was painted for, it is only painted again when it changes
+    painted: Option<Placement>,
+}
+
+impl Scaler {
+    pub fn new(options: ScaleOptions) -> Self {
+        Scaler {
+            options,
+            placement: None,
+            rgb: Vec::new(),
+            inner: Vec::new(),
+            canvas: Vec::new(),
+            painted: None,
+        }
+    }
+
+    //Changes the output size, None goes back to the size of the captured frames
+    pub fn set_size(&mut self, size: Option<Size>) {
+        self.options.size = size;
+    }
+
+    //Resizes the frame to the output size times scale, the frame is returned as it is if it already has that size
+    pub fn resize<'a>(&'a mut self, frame: Frame<'a>, scale: f32) -> Frame<'a> {
+        let size = self.options.size.unwrap_or(Size {
+            width: frame.width,
+            height: frame.height,
+        });
+        let width = ((size.width as f32 * scale).round() as u32).max(1);
+        let height = ((size.height as f32 * scale).round() as u32).max(1);
+        let output = Size { width, height };
+        if width == frame.width && height == frame.height {
+            self.placement = Some(Placement {
+                crop: Area::whole(width, height),
+                content: Area::whole(width, height),
+                output,
+            });
+            return frame;
+        }
+
+        let (frame_width, frame_height) = (frame.width as u64, frame.height as u64);
+        let (width64, height64) = (width as u64, height as u64);
+        let bytes_per_pixel = frame.format.bytes_per_pixel();
+        match self.options.mode {
+            ScaleMode::Stretch => {
+                self.placement = Some(Placement {
+                    crop: Area::whole(frame.width, frame.height),
+                    content: Area::whole(width, height),
+                    output,
+                });
+                self.scale(&frame, width, height)
+            }
+            ScaleMode::Fill => {
+                //Only the centered part with the aspect ratio of the output is resized
+                let (crop_width, crop_height) = if frame_width * height64 > width64 * frame_height {
+                    ((frame_height * width64 / height64).max(1), frame_height)
+                } else {
+                    (frame_width, (frame_width * height64 / width64).max(1))
+                };
+                let x = ((frame_width - crop_width) / 2) as usize;
+                let y = ((frame_height - crop_height) / 2) as usize;
+                self.placement = Some(Placement {
+                    crop: Area {
+                        x: x as u32,
+                        y: y as u32,
+                        width: crop_width as u32,
+                        height: crop_height as u32,
+                    },
+                    content: Area::whole(width, height),
+                    output,
+                });
+                let crop = Frame {
+                    data: &frame.data[y * frame.pitch + x * bytes_per_pixel..],
+                    width: crop_width as u32,
+                    height: crop_height as u32,
+                    ..frame
+                };
+                self.scale(&crop, width, height)
+            }
+            ScaleMode::Fit => {
+                let (inner_width, inner_height) = if frame_width * height64 > width64 * frame_height {
+                    (width64, (frame_height * width64 / frame_width).max(1))
+                } else {
+                    ((frame_width * height64 / frame_height).max(1), height64)
+                };
+                self.scale(&frame, inner_width as u32, inner_height as u32);
+                //The scaled frame goes in the middle of a canvas of the letterbox color
+                let (inner_width, inner_height) = (inner_width as usize, inner_height as usize);
+                let (x, y) = ((width as usize - inner_width) / 2, (height as usize - inner_height) / 2);
+                let placement = Some(Placement {
+                    crop: Area::whole(frame.width, frame.height),
+                    content: Area {
+                        x: x as u32,
+                        y: y as u32,
+                        width: inner_width as u32,
+                        height: inner_height as u32,
+                    },
+                    output,
+                });
+                self.placement = placement;
+                let pitch = width as usize * 3;
+                if self.painted != placement {
+                    self.canvas.clear();
+                    for _ in 0..width as usize * height as usize {
+                        self.canvas.extend_from_slice(&self.options.letterbox.0);
+                    }
+                    self.painted = placement;
+                }
+                for (row, pixels) in self.inner.chunks_exact(inner_width * 3).enumerate() {
+                    let start = (y + row) * pitch + x * 3;
+                    self.canvas[start..start + pixels.len()].copy_from_slice(pixels);
+                }
+                Frame {
+                    data: &self.canvas,
+                    width,
+                    height,
+                    pitch,
+                    format: PixelFormat::Rgb,
+                }
+            }
+        }
+    }
+
+    //Pixel of the last frame given to resize under a point of the output, x and y are fractions of the output size.
+    //Points on the letterbox go to the closest edge of the frame, None before the first frame
+    pub fn frame_point(&self, x: f32, y: f32) -> Option<(u32, u32)> {
+        let placement = self.placement?;
+        let map = |fraction: f32, output: u32, content: (u32, u32), crop: (u32, u32)| {
+            let position = fraction.clamp(0.0, 1.0) * output as f32;
+            let inside = ((position - content.0 as f32) / content.1 as f32).clamp(0.0, 1.0);
+            crop.0 + ((inside * crop.1 as f32) as u32).min(crop.1 - 1)
+        };
+        let (crop, content) = (placement.crop, placement.content);
+        Some((
+            map(x, placement.output.width, (content.x, content.width), (crop.x, crop.width)),
+            map(y, placement.output.height, (content.y, content.height), (crop.y, crop.height)),
+        ))
+    }
+
+    fn scale(&mut self, frame: &Frame, width: u32, height: u32) -> Frame<'_> {
+        pack_rgb(frame, &mut self.rgb);
+        let image = ImageBuffer::<Rgb<u8>, &[u8]>::from_raw(frame.width, frame.height, &self.rgb[..])
+            .expect("pack_rgb writes exactly width * height pixels");
+        //The image crate always resizes into a new buffer
+        self.inner = imageops::resize(&image, width, height, self.options.filter.image()).into_raw();
+        Frame {
+            data: &self.inner,
+            width,
+            height,
+            pitch: width as usize * 3,
+            format: PixelFormat::Rgb,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(mode: ScaleMode, output: Size, x: f32, y: f32) -> (u32, u32) {
+        //200x100 frame
+        let data = vec![0u8; 200 * 100 * 3];
+        let frame = Frame {
+            data: &data,
+            width: 200,
+            height: 100,
+            pitch: 600,
+            format: PixelFormat::Rgb,
+        };
+        let mut scaler = Scaler::new(ScaleOptions {
+            size: Some(output),
+            mode,
+            ..Default::default()
+        });
+        scaler.resize(frame, 1.0);
+        scaler.frame_point(x, y).unwrap()
+    }
+
+    const SQUARE: Size = Size {
+        width: 100,
+        height: 100,
+    };
+
+    #[test]
+    fn fit_skips_the_letterbox() {
+        //The frame is drawn 100x50 from y 25, the letterbox above it goes to the top row
+        assert_eq!(point(ScaleMode::Fit, SQUARE, 0.5, 0.5), (100, 50));
+        assert_eq!(point(ScaleMode::Fit, SQUARE, 0.0, 0.1), (0, 0));
+        assert_eq!(point(ScaleMode::Fit, SQUARE, 1.0, 0.74), (199, 98));
+    }
+
+    #[test]
+    fn fill_skips_the_cut_sides() {
+        //Only x 50 to 150 of the frame is shown
+        assert_eq!(point(ScaleMode::Fill, SQUARE, 0.0, 0.0), (50, 0));
+        assert_eq!(point(ScaleMode::Fill, SQUARE, 1.0, 1.0), (149, 99));
+    }
+
+    //200x100 frame, the left half is red and the right half is green
+    fn halves(mode: ScaleMode, output: Size, frames: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for _ in 0..100 {
+            for x in 0..200 {
+                data.extend_from_slice(if x < 100 { &[255, 0, 0] } else { &[0, 255, 0] });
+            }
+        }
+        let frame = Frame {
+            data: &data,
+            width: 200,
+            height: 100,
+            pitch: 600,
+            format: PixelFormat::Rgb,
+        };
+        let mut scaler = Scaler::new(ScaleOptions {
+            size: Some(output),
+            mode,
+            filter: Filter::Nearest,
+            letterbox: Color([0, 0, 255]),
+        });
+        for _ in 1..frames {
+            scaler.resize(frame, 1.0);
+        }
+        let resized = scaler.resize(frame, 1.0);
+        assert_eq!(
+            (resized.width, resized.height, resized.pitch),
+            (output.width, output.height, output.width as usize * 3)
+        );
+        resized.data.to_vec()
+    }
+
+    fn pixel(data: &[u8], width: usize, x: usize, y: usize) -> &[u8] {
+        &data[(y * width + x) * 3..][..3]
+    }
+
+    #[test]
+    fn fit_geometry() {
+        //Both frames give the same canvas, the letterbox painted for the first one is kept
+        for frames in [1, 2] {
+            let data = halves(ScaleMode::Fit, SQUARE, frames);
+            assert_eq!(data.len(), 100 * 100 * 3);
+            assert_eq!(pixel(&data, 100, 10, 24), [0, 0, 255]);
+            assert_eq!(pixel(&data, 100, 10, 25), [255, 0, 0]);
+            assert_eq!(pixel(&data, 100, 90, 74), [0, 255, 0]);
+            assert_eq!(pixel(&data, 100, 90, 75), [0, 0, 255]);
+        }
+        //Taller than the frame, the letterbox is on the sides
+        let tall = Size { width: 100, height: 25 };
+        let data = halves(ScaleMode::Fit, tall, 1);
+        assert_eq!(pixel(&data, 100, 24, 10), [0, 0, 255]);
+        assert_eq!(pixel(&data, 100, 25, 10), [255, 0, 0]);
+        assert_eq!(pixel(&data, 100, 74, 10), [0, 255, 0]);
+        assert_eq!(pixel(&data, 100, 75, 10), [0, 0, 255]);
+    }
+
+    #[test]
+    fn fill_geometry() {
+        //x 50 to 150 of the frame covers the output, no letterbox
+        let data = halves(ScaleMode::Fill, SQUARE, 1);
+        assert_eq!(pixel(&data, 100, 0, 0), [255, 0, 0]);
+        assert_eq!(pixel(&data, 100, 49, 99), [255, 0, 0]);
+        assert_eq!(pixel(&data, 100, 50, 0), [0, 255, 0]);
+        assert_eq!(pixel(&data, 100, 99, 99), [0, 255, 0]);
+    }
+
+    #[test]
+    fn parse_options() {
+        assert_eq!(
+            "1280x720".parse(),
+            Ok(Size {
+                width: 1280,
+                height: 720
+            })
+        );
+        assert_eq!(
+            " 640 X 480 ".parse(),
+            Ok(Size {
+                width: 640,
+                height: 480
+            })
+        );
+        assert!("1280".parse::<Size>().is_err());
+        assert!("0x720".parse::<Size>().is_err());
+        assert!("-1x720".parse::<Size>().is_err());
+
+        assert_eq!("#FF8000".parse(), Ok(Color([255, 128, 0])));
+        assert_eq!("00ff00".parse(), Ok(Color([0, 255, 0])));
+        assert!("fff".parse::<Color>().is_err());
+        assert!("GG0000".parse::<Color>().is_err());
+        assert!("+FFFFF".parse::<Color>().is_err());
+
+        assert_eq!("Fill".parse(), Ok(ScaleMode::Fill));
+        assert_eq!("stretch".parse(), Ok(ScaleMode::Stretch));
+        assert!("zoom".parse::<ScaleMode>().is_err());
+    }
+
+    #[test]
+    fn stretch_and_same_size() {
+        assert_eq!(point(ScaleMode::Stretch, SQUARE, 0.25, 0.5), (50, 50));
+        let same = Size {
+            width: 200,
+            height: 100,
+        };
+        assert_eq!(point(ScaleMode::Fit, same, 0.5, 0.25), (100, 25));
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::capture::{CaptureOptions, CaptureTarget, Rect, ScreenCapturer};
+use crate::error::{GabinatorError, Logger, LoggerLevel};
+use crate::frame::{Frame, PixelFormat};
+
+//Synthetic sources are paced to this rate, the screen is paced by the X server instead
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+//Size of the test pattern when no region is given
+const PATTERN_WIDTH: u16 = 800;
+const PATTERN_HEIGHT: u16 = 480;
+
+//Anything that produces frames for the pipeline
+pub trait FrameSource {
+    //Next frame, None if nothing changed since the last one
+    fn next_frame(&mut self) -> Result<Option<Frame<'_>>, GabinatorError>;
+    //The next frame is produced even if nothing changed, used when a new receiver connects
+    fn invalidate(&mut self);
+    //Changed parts of the last frame, relative to the frame
+    fn dirty_rects(&self) -> &[Rect];
+    //Root coordinates of the top left corner of the frames, None when they are not on the desktop
+    fn desktop_origin(&self) -> Option<(i32, i32)> {
+        None
+    }
+}
+
+//Where the frames come from
+#[derive(Clone, Debug, PartialEq)]
+pub enum SourceKind {
+    //The real screen, X11 or GDI
+    Screen,
+    //Generated color bars with a frame counter and a timestamp
+    TestPattern,
+    //PNG and JPEG files of a directory, replayed in name order
+    Directory(PathBuf),
+}
+
+impl FromStr for SourceKind {
+    type Err = String;
+
+    //screen, pattern or dir:<path>
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "screen" => Ok(SourceKind::Screen),
+            "pattern" => Ok(SourceKind::TestPattern),
+            _ => match s.strip_prefix("dir:") {
+                Some(path) if !path.is_empty() => Ok(SourceKind::Directory(PathBuf::from(path))),
+                _ => Err(format!("Not a valid source {s}, use screen, pattern or dir:<path>")),
+            },
+        }
+    }
+}
+
+//Opens the source chosen in the options
+pub fn open_source(options: CaptureOptions) -> Result<Box<dyn FrameSource>, GabinatorError> {
+    match options.source.clone() {
+        SourceKind::Screen => Ok(Box::new(ScreenCapturer::new(options)?)),
+        SourceKind::TestPattern => {
+            let (width, height) = match options.target {
+                CaptureTarget::Region(a) => (a.width, a.height),
+                _ => (PATTERN_WIDTH, PATTERN_HEIGHT),
+            };
+            Ok(Box::new(TestPattern::new(width, height)))
+        }
+        SourceKind::Directory(path) => Ok(Box::new(DirectorySource::new(path)?)),
+    }
+}
+
+//Waits until a frame interval passed since last, so synthetic sources dont flood the receiver
+fn pace(last: &mut Option<Instant>) {
+    if let Some(a) = last {
+        if let Some(wait) = FRAME_INTERVAL.checked_sub(a.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+    *last = Some(Instant::now());
+}
+
+fn full_frame(width: u16, height: u16) -> Vec<Rect> {
+    vec![Rect {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    }]
+}
+
+//3x5 glyphs for the overlay, one row per byte, the 3 low bits are the pixels from left to right
+fn glyph(character: char) -> [u8; 5] {
+    match character {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        _ => [0; 5],
+    }
+}
+
+//Deterministic color bars, everything but the timestamp depends only on the frame counter
+pub struct TestPattern {
+    width: u16,
+    height: u16,
+    counter: u64,
+    pixels: Vec<u8>,
+    dirty: Vec<Rect>,
+    last: Option<Instant>,
+}
+
+impl TestPattern {
+    pub fn new(width: u16, height: u16) -> Self {
+        TestPattern {
+            width,
+            height,
+            counter: 0,
+            pixels: vec![0; width as usize * height as usize * 4],
+            dirty: full_frame(width, height),
+            last: None,
+        }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+        let frame_width = self.width as usize;
+        let frame_height = self.height as usize;
+        for row in y.min(frame_height)..(y + height).min(frame_height) {
+            let start = (row * frame_width + x.min(frame_width)) * 4;
+            let end = (row * frame_width + (x + width).min(frame_width)) * 4;
+            for pixel in self.pixels[start..end].chunks_exact_mut(4) {
+                pixel.copy_from_slice(&[color[0], color[1], color[2], 255]);
+            }
+        }
+    }
+
+    fn text(&mut self, x: usize, y: usize, scale: usize, text: &str) {
+        //Black box behind the text so it can be read over any bar
+        let width = text.chars().count() * 4 * scale + scale;
+        self.fill(x, y, width, 7 * scale, [0, 0, 0]);
+        for (index, character) in text.chars().enumerate() {
+            for (row, bits) in glyph(character).iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        self.fill(
+                            x + scale + (index * 4 + column) * scale,
+                            y + scale + row * scale,
+                            scale,
+                            scale,
+                            [255, 255, 255],
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self) {
+        const BARS: [[u8; 3]; 8] = [
+            [255, 255, 255],
+            [255, 255, 0],
+            [0, 255, 255],
+            [0, 255, 0],
+            [255, 0, 255],
+            [255, 0, 0],
+            [0, 0, 255],
+            [0, 0, 0],
+        ];
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let bars_height = height * 2 / 3;
+        for (index, color) in BARS.iter().enumerate() {
+            let x = index * width / BARS.len();
+            let next = (index + 1) * width / BARS.len();
+            self.fill(x, 0, next - x, bars_height, *color);
+        }
+        //Gray ramp under the bars
+        for x in 0..width {
+            let level = (x * 255 / width.max(2).saturating_sub(1)).min(255) as u8;
+            self.fill(x, bars_height, 1, height - bars_height, [level, level, level]);
+        }
+        //Moving marker, so every frame differs from the previous one
+        let marker = (self.counter as usize * 8) % width.max(1);
+        self.fill(marker, bars_height, 8, height - bars_height, [255, 0, 0]);
+
+        let scale = (height / 60).max(1);
+        let counter = format!("{:08}", self.counter);
+        let timestamp = chrono::Local::now().format("%H:%M:%S%.3f").to_string();
+        self.text(scale * 2, scale * 2, scale, &counter);
+        self.text(scale * 2, scale * 10, scale, &timestamp);
+    }
+}
+
+impl FrameSource for TestPattern {
+    fn next_frame(&mut self) -> Result<Option<Frame<'_>>, GabinatorError> {
+        pace(&mut self.last);
+        self.draw();
+        self.counter += 1;
+        Ok(Some(Frame {
+            data: &self.pixels,
+            width: self.width as u32,
+            height: self.height as u32,
+            pitch: self.width as usize * 4,
+            format: PixelFormat::Rgba,
+        }))
+    }
+
+    //Every frame is new anyway
+    fn invalidate(&mut self) {}
+
+    fn dirty_rects(&self) -> &[Rect] {
+        &self.dirty
+    }
+}
+
+//Replays the images of a directory in a loop
+pub struct DirectorySource {
+    files: Vec<PathBuf>,
+    index: usize,
+    pixels: Vec<u8>,
+    width: u32,
+    height: u32,
+    dirty: Vec<Rect>,
+    last: Option<Instant>,
+    config: HashMap<String, String>,
+}
+
+impl DirectorySource {
+    pub fn new(path: PathBuf) -> Result<Self, GabinatorError> {
+        let config = Logger::get_config_content();
+        let entries = match std::fs::read_dir(&path) {
+            Ok(a) => a,
+            Err(a) => {
+                return Err(GabinatorError::newCapture(
+                    format!("Not able to read the directory {}: {a}", path.display()),
+                    LoggerLevel::Error,
+                    Some(config),
+                ))
+            }
+        };
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|a| a.ok().map(|b| b.path()))
+            .filter(|a| {
+                a.extension()
+                    .and_then(|b| b.to_str())
+                    .map(|b| ["png", "jpg", "jpeg"].contains(&b.to_lowercase().as_str()))
+                    .unwrap_or(false)
+            })
+            .collect();
+        if files.is_empty() {
+            return Err(GabinatorError::newCapture(
+                format!("There are no PNG or JPEG files in {}", path.display()),
+                LoggerLevel::Error,
+                Some(config),
+            ));
+        }
+        files.sort();
+        Logger::log(
+            format!("Replaying {} images from {}", files.len(), path.display()),
+            LoggerLevel::Debug,
+            Some(config.clone()),
+        );
+        Ok(DirectorySource {
+            files,
+            index: 0,
+            pixels: Vec::new(),
+            width: 0,
+            height: 0,
+            dirty: Vec::new(),
+            last: None,
+            config,
+        })
+    }
+}
+
+impl FrameSource for DirectorySource {
+    fn next_frame(&mut self) -> Result<Option<Frame<'_>>, GabinatorError> {
+        pace(&mut self.last);
+        let path = &self.files[self.index];
+        self.index = (self.index + 1) % self.files.len();
+        let image = match image::open(path) {
+            Ok(a) => a.into_rgba8(),
+            Err(a) => {
+                return Err(GabinatorError::newCapture(
+                    format!("Not able to decode {}: {a}", path.display()),
+                    LoggerLevel::Error,
+                    Some(self.config.clone()),
+                ))
+            }
+        };
+        //The dirty rectangles and the frame header have 16 bit sizes
+        let (width, height) = match (u16::try_from(image.width()), u16::try_from(image.height())) {
+            (Ok(a), Ok(b)) => (a, b),
+            _ => {
+                return Err(GabinatorError::newCapture(
+                    format!("{} is too big: {}x{}", path.display(), image.width(), image.height()),
+                    LoggerLevel::Error,
+                    Some(self.config.clone()),
+                ))
+            }
+        };
+        self.width = image.width();
+        self.height = image.height();
+        self.pixels = image.into_raw();
+        self.dirty = full_frame(width, height);
+        Ok(Some(Frame {
+            data: &self.pixels,
+            width: self.width,
+            height: self.height,
+            pitch: self.width as usize * 4,
+            format: PixelFormat::Rgba,
+        }))
+    }
+
+    //Every image is sent whole
+    fn invalidate(&mut self) {}
+
+    fn dirty_rects(&self) -> &[Rect] {
+        &self.dirty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{new_encoder, Codec, EncoderOptions};
+
+    //The whole encode path runs without a display, like in CI
+    #[test]
+    fn test_pattern_encodes_changing_frames() {
+        let mut source = TestPattern::new(160, 96);
+        for codec in [Codec::Jpeg, Codec::Png] {
+            let mut encoder = new_encoder(&EncoderOptions {
+                codec,
+                ..Default::default()
+            })
+            .unwrap();
+            let mut outputs = Vec::new();
+            for _ in 0..3 {
+                let frame = source.next_frame().unwrap().unwrap();
+                assert_eq!((frame.width, frame.height), (160, 96));
+                outputs.push(encoder.encode(&frame).unwrap());
+                assert_eq!(source.dirty_rects().len(), 1);
+            }
+            assert!(outputs.iter().all(|a| !a.is_empty()));
+            assert_ne!(outputs[0], outputs[1]);
+            assert_ne!(outputs[1], outputs[2]);
+        }
+    }
+}
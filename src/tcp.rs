@@ -4,19 +4,46 @@ use std::fs::File;
 use turbojpeg::Image;
 
 use crate::Logger;
-use crate::{capture::capture_screen, error::GabinatorError};
+use crate::{
+    capture::CaptureOptions,
+    encoder::{Codec, EncoderOptions},
+    error::GabinatorError,
+    handshake::{negotiate, HELLO_TIMEOUT},
+    message::{codec_message, rejection_message, welcome_message, ClientMessage, MessageReader},
+    pipeline::Pipeline,
+    protocol::{FrameHeader, HEADER_LEN, VERSION},
+};
 use std::io::Read;
 use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::Instant;
 use std::{io::Write, net::TcpListener};
 
-pub fn start_server( test_server: bool, test_data: bool,quality: u8) {
+pub fn start_server( test_server: bool, test_data: bool, encoder_options: EncoderOptions, options: CaptureOptions) {
     let config = Logger::get_config_content();
     let socket = TcpListener::bind("0.0.0.0:3000").unwrap();
     let ip = local_ip_address::local_ip().unwrap();
     println!("SERVER IP -> {:?}", ip);
+    //The pipeline is shared by every client, so the X connection is opened only once
+    let mut pipeline = match Pipeline::new(&encoder_options, options) {
+        Ok(a) => a,
+        Err(a) => {
+            GabinatorError::newCapture(
+                format!("Not able to start the pipeline: {a:?}"),
+                crate::error::LoggerLevel::Critical,
+                Some(config.clone()),
+            );
+            return;
+        }
+    };
     for st in socket.incoming() {
         println!("Conectado");
         let mut client = st.unwrap();
+        let messages = spawn_reader(&client, config.clone());
+        if !test_data && !greet(&mut client, &messages, &mut pipeline, &encoder_options, config.clone()) {
+            pipeline.client_disconnected();
+            continue;
+        }
         let mut tries = 0;
         if test_server{
             if test_data{
@@ -27,7 +54,9 @@ pub fn start_server( test_server: bool, test_data: bool,quality: u8) {
                         Some(config.clone()));
                     }
             }
-            else{send_image_data(config.clone(),&mut client,quality);}
+            else if handle_messages(&mut pipeline, &messages) {
+                send_image_data(config.clone(), &mut client, &mut pipeline);
+            }
         }
         else{
             loop {
@@ -40,7 +69,11 @@ pub fn start_server( test_server: bool, test_data: bool,quality: u8) {
                         }
                 }
                 else{
-                    let value = send_image_data(config.clone(),&mut client,quality);
+                    //The session ends with the client, even if the frames could still be written
+                    if !handle_messages(&mut pipeline, &messages) {
+                        break;
+                    }
+                    let value = send_image_data(config.clone(), &mut client, &mut pipeline);
                 if value.is_some() {
                     tries += 1;
                     println!("Package failed, {} more tries remaining", 5 - tries);
@@ -54,18 +87,141 @@ pub fn start_server( test_server: bool, test_data: bool,quality: u8) {
                 }
             }
         }
+        //Keys and buttons the client left held down are released for the next one
+        pipeline.client_disconnected();
         
     }
 }
 
-fn send_image_data(config: HashMap<String,String>,  client: &mut TcpStream, quality: u8) -> Option<GabinatorError> {
-    let mut data = capture_screen(quality).unwrap();
-    let mut pdata = Vec::new();
-    pdata.push(u8::try_from(usize::BITS).unwrap());
-    pdata.append(&mut  data.len().to_be_bytes().to_vec());
-    pdata.append(&mut data);
+//Waits for the hello of the client and sets the pipeline up for it, false if the client has to be dropped.
+//Clients that send no hello get the options of the server and only the codec
+fn greet(
+    client: &mut TcpStream,
+    messages: &Receiver<ClientMessage>,
+    pipeline: &mut Pipeline,
+    encoder_options: &EncoderOptions,
+    config: HashMap<String, String>,
+) -> bool {
+    let mut pending = None;
+    let negotiated = match messages.recv_timeout(HELLO_TIMEOUT) {
+        Ok(ClientMessage::Hello(hello)) => match negotiate(&hello, encoder_options) {
+            Ok(a) => Some(a),
+            Err(a) => {
+                GabinatorError::newCapture(a, crate::error::LoggerLevel::Error, Some(config));
+                //The receiver is told why the connection closes, the write can fail as it is dropped anyway
+                let _ = client.write_all(&rejection_message(hello.version.clamp(1, VERSION)));
+                return false;
+            }
+        },
+        Ok(a) => {
+            pending = Some(a);
+            None
+        }
+        Err(_) => None,
+    };
+    let client_options = negotiated.as_ref().map(|a| &a.1).unwrap_or(encoder_options);
+    if let Err(a) = pipeline.reconfigure(client_options) {
+        GabinatorError::newCapture(
+            format!("Not able to set the pipeline up for the client: {a:?}"),
+            crate::error::LoggerLevel::Error,
+            Some(config),
+        );
+        return false;
+    }
+    if let Some(a) = pending {
+        pipeline.handle_message(a);
+    }
+    //The new client needs a whole frame to start with
+    pipeline.invalidate();
+    //Clients without a hello get the frames of the latest version, as before the hello existed
+    pipeline.set_version(negotiated.as_ref().map(|a| a.0).unwrap_or(VERSION));
+    let greeting = match &negotiated {
+        Some((version, _)) => welcome_message(*version, pipeline.applied_options()),
+        None => codec_message(pipeline.codec()),
+    };
+    if client.write_all(&greeting).is_err() {
+        GabinatorError::newCapture(
+            "Error sending the codec",
+            crate::error::LoggerLevel::Error,
+            Some(config),
+        );
+        return false;
+    }
+    true
+}
 
-    if client.write_all(&pdata).is_err() {
+//Reads the messages of the receiver in their own thread, the channel closes when the client disconnects
+fn spawn_reader(client: &TcpStream, config: HashMap<String, String>) -> Receiver<ClientMessage> {
+    let (sender, receiver) = mpsc::channel();
+    let mut stream = match client.try_clone() {
+        Ok(a) => a,
+        Err(a) => {
+            GabinatorError::newCapture(
+                format!("Not able to read from the client, its messages are ignored: {a}"),
+                crate::error::LoggerLevel::Warning,
+                Some(config),
+            );
+            return receiver;
+        }
+    };
+    std::thread::spawn(move || {
+        let mut reader = MessageReader::default();
+        let mut buffer = [0u8; 1024];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) | Err(_) => return,
+                Ok(a) => reader.push(&buffer[..a]),
+            }
+            while let Some(message) = reader.next_message() {
+                match message {
+                    Ok(a) => {
+                        if sender.send(a).is_err() {
+                            return;
+                        }
+                    }
+                    Err(a) => Logger::log(a, crate::error::LoggerLevel::Warning, Some(config.clone())),
+                }
+            }
+        }
+    });
+    receiver
+}
+
+//Applies the messages the client sent since the last frame, false once the client is gone and its reader stopped
+fn handle_messages(pipeline: &mut Pipeline, messages: &Receiver<ClientMessage>) -> bool {
+    loop {
+        match messages.try_recv() {
+            Ok(a) => pipeline.handle_message(a),
+            Err(TryRecvError::Empty) => return true,
+            Err(TryRecvError::Disconnected) => return false,
+        }
+    }
+}
+
+fn send_image_data(config: HashMap<String,String>,  client: &mut TcpStream, pipeline: &mut Pipeline) -> Option<GabinatorError> {
+    let packet = match pipeline.next_frame() {
+        Ok(Some(a)) => a,
+        //Nothing changed on the screen
+        Ok(None) => return None,
+        Err(a) => return Some(a),
+    };
+    Logger::log(
+        format!("Sending frame, {} areas changed", pipeline.dirty_rects().len()),
+        crate::error::LoggerLevel::Debug,
+        Some(config.clone()),
+    );
+    //Header and frame are written apart, so the frame buffer can go back to the pipeline
+    let start = Instant::now();
+    let result = match packet.header() {
+        Some(header) => client.write_all(&header).and_then(|_| client.write_all(packet.data())),
+        //Messages carry their own header
+        None => client.write_all(packet.data()),
+    };
+    if result.is_ok() {
+        pipeline.report_sent(packet.data().len(), start.elapsed());
+    }
+    pipeline.recycle(packet);
+    if result.is_err() {
         return Some(GabinatorError::newCapture(format!("Error writing data into client"), crate::error::LoggerLevel::Error, Some(config.clone())))
     }
     /*if client.flush().is_err() {
@@ -78,24 +234,66 @@ fn send_image_data(config: HashMap<String,String>,  client: &mut TcpStream, qual
     None
 }
 
+//Debug receiver, saves every frame the server sends in its own file
 pub fn test_server() {
     let config = Logger::get_config_content();
     let mut server = TcpStream::connect("0.0.0.0:3000").unwrap();
-    let mut total_buffer = File::create("amongas.jpg").unwrap();
-    let mut total_bytes = 0;
-    let mut iteration = 0;
+    let mut stream = Vec::new();
+    let mut buffer = [0; 1024];
     loop {
-        let mut buffer = [0; 1024];
         let bytes = server.read(&mut buffer).unwrap();
-        total_buffer.write_all(&buffer);
-        total_bytes += bytes;
-        dbg!(&bytes);
-        if bytes < 1024 {
-            let mut test = String::new();
-            dbg!(&total_bytes);
-            total_bytes = 0;
-            iteration += 1;
-            total_buffer = File::create(format!("amongas{iteration}.jpg")).unwrap();
+        if bytes == 0 {
+            return;
+        }
+        stream.extend_from_slice(&buffer[..bytes]);
+        loop {
+            //Control messages, [0][kind][payload length u32][payload]
+            if stream.first() == Some(&0) {
+                if stream.len() < 6 {
+                    break;
+                }
+                let len = 6 + u32::from_be_bytes(stream[2..6].try_into().unwrap()) as usize;
+                if stream.len() < len {
+                    break;
+                }
+                Logger::log(
+                    format!("Message of kind {} and {len} bytes", stream[1]),
+                    crate::error::LoggerLevel::Debug,
+                    Some(config.clone()),
+                );
+                stream.drain(..len);
+                continue;
+            }
+            if stream.len() < HEADER_LEN {
+                break;
+            }
+            let header = match FrameHeader::decode(&stream) {
+                Ok(a) => a,
+                Err(a) => {
+                    Logger::log(
+                        format!("Not a valid frame header: {a:?}"),
+                        crate::error::LoggerLevel::Debug,
+                        Some(config.clone()),
+                    );
+                    return;
+                }
+            };
+            let len = HEADER_LEN + header.payload_length as usize;
+            if stream.len() < len {
+                break;
+            }
+            Logger::log(format!("{header:?}"), crate::error::LoggerLevel::Debug, Some(config.clone()));
+            let extension = match header.codec {
+                Codec::Jpeg | Codec::JpegGray => "jpg",
+                Codec::Png => "png",
+                Codec::Webp => "webp",
+                Codec::Qoi => "qoi",
+                Codec::H264 => "h264",
+                Codec::Gray4 | Codec::Mono | Codec::Rgb565 => "raw",
+            };
+            let mut file = File::create(format!("amongas{}.{extension}", header.frame_id)).unwrap();
+            file.write_all(&stream[HEADER_LEN..len]).unwrap();
+            stream.drain(..len);
         }
     }
 }
@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+
+use crate::encoder::{BufferPool, Encoder};
+use crate::frame::Frame;
+use crate::message::{begin_message, finish_message, TILE_UPDATE};
+
+//Tile updates kept while waiting for their ack, older ones can not be acknowledged anymore
+const MAX_IN_FLIGHT: usize = 32;
+
+//Hash of the bytes of one tile, FNV-1a over 8 byte words
+fn hash_tile(frame: &Frame, x: usize, y: usize, width: usize, height: usize) -> u64 {
+    const PRIME: u64 = 0x100000001b3;
+    let bytes_per_pixel = frame.format.bytes_per_pixel();
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for row in y..y + height {
+        let start = row * frame.pitch + x * bytes_per_pixel;
+        let bytes = &frame.data[start..start + width * bytes_per_pixel];
+        let mut words = bytes.chunks_exact(8);
+        for word in &mut words {
+            hash = (hash ^ u64::from_le_bytes(word.try_into().unwrap())).wrapping_mul(PRIME);
+        }
+        for byte in words.remainder() {
+            hash = (hash ^ *byte as u64).wrapping_mul(PRIME);
+        }
+    }
+    hash
+}
+
+//Splits the frames in tiles and sends only the ones that changed since the last acknowledged frame
+pub struct TileEncoder {
+    size: u16,
+    next_id: u32,
+    //Size of the frames the hashes belong to
+    width: u32,
+    height: u32,
+    //Hashes of the frame being encoded
+    hashes: Vec<u64>,
+    //Hashes of the last tile update that was sent
+    sent: Vec<u64>,
+    //Hashes the receiver is known to have drawn, None until the first ack
+    acked: Option<Vec<u64>>,
+    in_flight: VecDeque<(u32, Vec<u64>)>,
+    pool: BufferPool,
+}
+
+impl TileEncoder {
+    pub fn new(size: u16) -> Self {
+        TileEncoder {
+            size: size.max(8),
+            next_id: 0,
+            width: 0,
+            height: 0,
+            hashes: Vec::new(),
+            sent: Vec::new(),
+            acked: None,
+            in_flight: VecDeque::new(),
+            pool: BufferPool::default(),
+        }
+    }
+
+    //Forgets what the receiver has, the next update has every tile
+    pub fn reset(&mut self) {
+        self.sent.clear();
+        self.acked = None;
+        self.in_flight.clear();
+    }
+
+    //The receiver drew update id, the ones before it are not waited for anymore. Unknown ids are ignored
+    pub fn acknowledge(&mut self, id: u32) {
+        if let Some(position) = self.in_flight.iter().position(|a| a.0 == id) {
+            self.acked = self.in_flight.drain(..=position).next_back().map(|a| a.1);
+        }
+    }
+
+    //The receiver has hash at index once the updates in flight arrive
+    fn receiver_has(&self, index: usize, hash: u64) -> bool {
+        self.acked.as_ref().is_some_and(|a| a[index] == hash) && self.in_flight.iter().all(|a| a.1[index] == hash)
+    }
+
+    //Tile update message with the changed tiles, None if the frame is the same as the last one sent
+    pub fn encode(&mut self, frame: &Frame, encoder: &mut dyn Encoder) -> Result<Option<Vec<u8>>, String> {
+        if frame.width != self.width || frame.height != self.height {
+            self.width = frame.width;
+            self.height = frame.height;
+            self.reset();
+        }
+
+        let size = self.size as usize;
+        let columns = (frame.width as usize).div_ceil(size);
+        let rows = (frame.height as usize).div_ceil(size);
+        self.hashes.clear();
+        for row in 0..rows {
+            for column in 0..columns {
+                let (x, y) = (column * size, row * size);
+                let width = size.min(frame.width as usize - x);
+                let height = size.min(frame.height as usize - y);
+                self.hashes.push(hash_tile(frame, x, y, width, height));
+            }
+        }
+        if self.hashes == self.sent {
+            return Ok(None);
+        }
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut message = self.pool.take(0);
+        begin_message(TILE_UPDATE, &mut message);
+        message.extend_from_slice(&id.to_be_bytes());
+        message.extend_from_slice(&(frame.width as u16).to_be_bytes());
+        message.extend_from_slice(&(frame.height as u16).to_be_bytes());
+        let count_index = message.len();
+        message.extend_from_slice(&[0, 0, 0, 0]);
+
+        let bytes_per_pixel = frame.format.bytes_per_pixel();
+        let mut count: u32 = 0;
+        for (index, hash) in self.hashes.iter().enumerate() {
+            //A tile is only left out if no update on the way changes it
+            if self.receiver_has(index, *hash) {
+                continue;
+            }
+            let (x, y) = ((index % columns) * size, (index / columns) * size);
+            let width = size.min(frame.width as usize - x);
+            let height = size.min(frame.height as usize - y);
+            let tile = Frame {
+                data: &frame.data[y * frame.pitch + x * bytes_per_pixel..],
+                width: width as u32,
+                height: height as u32,
+                pitch: frame.pitch,
+                format: frame.format,
+            };
+            let encoded = match encoder.encode(&tile) {
+                Ok(a) => a,
+                Err(a) => {
+                    self.pool.give_back(message);
+                    return Err(a);
+                }
+            };
+            for value in [x as u16, y as u16, width as u16, height as u16] {
+                message.extend_from_slice(&value.to_be_bytes());
+            }
+            message.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+            message.extend_from_slice(&encoded);
+            encoder.recycle(encoded);
+            count += 1;
+        }
+        message[count_index..count_index + 4].copy_from_slice(&count.to_be_bytes());
+        finish_message(&mut message);
+
+        self.sent.clone_from(&self.hashes);
+        if self.in_flight.len() == MAX_IN_FLIGHT {
+            //The receiver can not be trusted to have the acked tiles without this update, every tile is sent again
+            self.in_flight.pop_front();
+            self.acked = None;
+        }
+        self.in_flight.push_back((id, self.hashes.clone()));
+        Ok(Some(message))
+    }
+
+    pub fn recycle(&mut self, buffer: Vec<u8>) {
+        self.pool.give_back(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Codec;
+    use crate::frame::PixelFormat;
+
+    //Writes the first byte of the tile, enough to tell the tiles apart
+    struct FirstByte;
+
+    impl Encoder for FirstByte {
+        fn codec(&self) -> Codec {
+            Codec::Png
+        }
+
+        fn encode(&mut self, frame: &Frame) -> Result<Vec<u8>, String> {
+            Ok(vec![frame.data[0]])
+        }
+
+        fn recycle(&mut self, _buffer: Vec<u8>) {}
+    }
+
+    //16x8 RGB frame, two 8x8 tiles filled with left and right
+    fn frame(left: u8, right: u8) -> Vec<u8> {
+        (0..8).flat_map(|_| [[left; 24], [right; 24]].concat()).collect()
+    }
+
+    fn encode(tiles: &mut TileEncoder, data: &[u8]) -> Option<Vec<u8>> {
+        let frame = Frame {
+            data,
+            width: 16,
+            height: 8,
+            pitch: 48,
+            format: PixelFormat::Rgb,
+        };
+        tiles.encode(&frame, &mut FirstByte).unwrap()
+    }
+
+    //Tile count of an update
+    fn count(message: &[u8]) -> u32 {
+        u32::from_be_bytes(message[14..18].try_into().unwrap())
+    }
+
+    #[test]
+    fn layout() {
+        let mut tiles = TileEncoder::new(8);
+        let message = encode(&mut tiles, &frame(1, 2)).unwrap();
+        let mut expected = vec![0, TILE_UPDATE, 0, 0, 0, 38];
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 16, 0, 8, 0, 0, 0, 2]);
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 8, 0, 8, 0, 0, 0, 1, 1]);
+        expected.extend_from_slice(&[0, 8, 0, 0, 0, 8, 0, 8, 0, 0, 0, 1, 2]);
+        assert_eq!(message, expected);
+        //Nothing changed
+        assert_eq!(encode(&mut tiles, &frame(1, 2)), None);
+    }
+
+    #[test]
+    fn only_changed_tiles_after_ack() {
+        let mut tiles = TileEncoder::new(8);
+        encode(&mut tiles, &frame(1, 2)).unwrap();
+        tiles.acknowledge(0);
+        let message = encode(&mut tiles, &frame(1, 3)).unwrap();
+        assert_eq!(count(&message), 1);
+        assert_eq!(message[message.len() - 1], 3);
+    }
+
+    //The right tile goes 2, 3, 2 with only the first update acked, the receiver must not keep 3
+    #[test]
+    fn tile_changed_back_while_in_flight() {
+        let mut tiles = TileEncoder::new(8);
+        encode(&mut tiles, &frame(1, 2)).unwrap();
+        tiles.acknowledge(0);
+        encode(&mut tiles, &frame(1, 3)).unwrap();
+        let message = encode(&mut tiles, &frame(1, 2)).unwrap();
+        assert_eq!(count(&message), 1);
+        assert_eq!(message[message.len() - 1], 2);
+    }
+
+    #[test]
+    fn unknown_ack_keeps_the_updates() {
+        let mut tiles = TileEncoder::new(8);
+        encode(&mut tiles, &frame(1, 2)).unwrap();
+        encode(&mut tiles, &frame(1, 3)).unwrap();
+        tiles.acknowledge(77);
+        assert_eq!(tiles.in_flight.len(), 2);
+        assert_eq!(tiles.acked, None);
+        tiles.acknowledge(1);
+        assert!(tiles.in_flight.is_empty());
+        //Update 1 is what the receiver has, only the left tile changes
+        let message = encode(&mut tiles, &frame(4, 3)).unwrap();
+        assert_eq!(count(&message), 1);
+    }
+}
@@ -0,0 +1,263 @@
+use std::str::FromStr;
+
+use crate::frame::Frame;
+
+//Clockwise rotation of the frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rotation {
+    R0,
+    R90,
+    R180,
+    R270,
+}
+
+impl Rotation {
+    //Rotation from its number of quarter turns, the receivers send it like that
+    pub fn from_quarters(quarters: u8) -> Self {
+        match quarters % 4 {
+            0 => Rotation::R0,
+            1 => Rotation::R90,
+            2 => Rotation::R180,
+            _ => Rotation::R270,
+        }
+    }
+
+    pub fn degrees(self) -> u16 {
+        match self {
+            Rotation::R0 => 0,
+            Rotation::R90 => 90,
+            Rotation::R180 => 180,
+            Rotation::R270 => 270,
+        }
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "0" => Ok(Rotation::R0),
+            "90" => Ok(Rotation::R90),
+            "180" => Ok(Rotation::R180),
+            "270" => Ok(Rotation::R270),
+            _ => Err(format!("Not a valid rotation {s}, use 0, 90, 180 or 270")),
+        }
+    }
+}
+
+//Rotation and mirroring of the frames, the flips are applied after the rotation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub rotation: Rotation,
+    //Mirrors left and right
+    pub flip_horizontal: bool,
+    //Mirrors top and bottom
+    pub flip_vertical: bool,
+}
+
+impl Transform {
+    //Pixel of a width x height frame that ends at x,y once the transform is applied
+    pub fn source_pixel(&self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        let (output_width, output_height) = match self.rotation {
+            Rotation::R0 | Rotation::R180 => (width, height),
+            Rotation::R90 | Rotation::R270 => (height, width),
+        };
+        let x = if self.flip_horizontal { output_width - 1 - x } else { x };
+        let y = if self.flip_vertical { output_height - 1 - y } else { y };
+        match self.rotation {
+            Rotation::R0 => (x, y),
+            Rotation::R90 => (y, height - 1 - x),
+            Rotation::R180 => (width - 1 - x, height - 1 - y),
+            Rotation::R270 => (width - 1 - y, x),
+        }
+    }
+
+    //Direction in the frame of a movement seen in the transformed frame
+    pub fn source_vector(&self, dx: i32, dy: i32) -> (i32, i32) {
+        let dx = if self.flip_horizontal { -dx } else { dx };
+        let dy = if self.flip_vertical { -dy } else { dy };
+        match self.rotation {
+            Rotation::R0 => (dx, dy),
+            Rotation::R90 => (dy, -dx),
+            Rotation::R180 => (-dx, -dy),
+            Rotation::R270 => (-dy, dx),
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            rotation: Rotation::R0,
+            flip_horizontal: false,
+            flip_vertical: false,
+        }
+    }
+}
+
+//Applies a transform to the frames, the output keeps the pixel format of the frame
+pub struct Transformer {
+    transform: Transform,
+    output: Vec<u8>,
+}
+
+impl Transformer {
+    pub fn new(transform: Transform) -> Self {
+        Transformer {
+            transform,
+            output: Vec::new(),
+        }
+    }
+
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.transform.rotation = rotation;
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.transform
+    }
+
+    pub fn apply<'a>(&'a mut self, frame: Frame<'a>) -> Frame<'a> {
+        if self.transform == Transform::default() {
+            return frame;
+        }
+        let (width, height) = (frame.width as usize, frame.height as usize);
+        let (output_width, output_height) = match self.transform.rotation {
+            Rotation::R0 | Rotation::R180 => (width, height),
+            Rotation::R90 | Rotation::R270 => (height, width),
+        };
+        let transform = self.transform;
+        let source = |x: usize, y: usize| transform.source_pixel(x, y, width, height);
+
+        let bytes_per_pixel = frame.format.bytes_per_pixel();
+        let offset = |(x, y): (usize, usize)| (y * frame.pitch + x * bytes_per_pixel) as isize;
+        let pitch = output_width * bytes_per_pixel;
+        self.output.resize(pitch * output_height, 0);
+        //Along an output row the source moves by the same number of bytes every pixel
+        let step = if output_width > 1 {
+            offset(source(1, 0)) - offset(source(0, 0))
+        } else {
+            0
+        };
+        for (y, row) in self.output.chunks_exact_mut(pitch).enumerate() {
+            let mut position = offset(source(0, y));
+            for pixel in row.chunks_exact_mut(bytes_per_pixel) {
+                let start = position as usize;
+                pixel.copy_from_slice(&frame.data[start..start + bytes_per_pixel]);
+                position += step;
+            }
+        }
+        Frame {
+            data: &self.output,
+            width: output_width as u32,
+            height: output_height as u32,
+            pitch,
+            format: frame.format,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::PixelFormat;
+
+    //Every pixel of a 3x2 frame has its own value, so source_pixel can be checked against apply
+    #[test]
+    fn source_pixel_matches_apply() {
+        let data: Vec<u8> = (0..6).flat_map(|a| [a; 3]).collect();
+        for quarters in 0..4 {
+            for flips in 0..4 {
+                let transform = Transform {
+                    rotation: Rotation::from_quarters(quarters),
+                    flip_horizontal: flips & 1 != 0,
+                    flip_vertical: flips & 2 != 0,
+                };
+                let frame = Frame {
+                    data: &data,
+                    width: 3,
+                    height: 2,
+                    pitch: 9,
+                    format: PixelFormat::Rgb,
+                };
+                let mut transformer = Transformer::new(transform);
+                let output = transformer.apply(frame);
+                for y in 0..output.height as usize {
+                    for x in 0..output.width as usize {
+                        let (source_x, source_y) = transform.source_pixel(x, y, 3, 2);
+                        assert_eq!(output.data[y * output.pitch + x * 3] as usize, source_y * 3 + source_x);
+                    }
+                }
+            }
+        }
+    }
+
+    //3x2 frame numbered 0 1 2 / 3 4 5, each output is written row by row
+    #[test]
+    fn apply_every_rotation_and_flip() {
+        let cases: [(Rotation, bool, bool, [u8; 6]); 12] = [
+            (Rotation::R0, false, false, [0, 1, 2, 3, 4, 5]),
+            (Rotation::R90, false, false, [3, 0, 4, 1, 5, 2]),
+            (Rotation::R180, false, false, [5, 4, 3, 2, 1, 0]),
+            (Rotation::R270, false, false, [2, 5, 1, 4, 0, 3]),
+            (Rotation::R0, true, false, [2, 1, 0, 5, 4, 3]),
+            (Rotation::R90, true, false, [0, 3, 1, 4, 2, 5]),
+            (Rotation::R180, true, false, [3, 4, 5, 0, 1, 2]),
+            (Rotation::R270, true, false, [5, 2, 4, 1, 3, 0]),
+            (Rotation::R0, false, true, [3, 4, 5, 0, 1, 2]),
+            (Rotation::R90, false, true, [5, 2, 4, 1, 3, 0]),
+            (Rotation::R180, false, true, [2, 1, 0, 5, 4, 3]),
+            (Rotation::R270, false, true, [0, 3, 1, 4, 2, 5]),
+        ];
+        //4 bytes a pixel and a padded pitch
+        let mut data = vec![0xEE; 2 * 16];
+        for a in 0..6 {
+            data[a / 3 * 16 + a % 3 * 4..][..4].copy_from_slice(&[a as u8, 0, 0, 0xFF]);
+        }
+        for (rotation, flip_horizontal, flip_vertical, expected) in cases {
+            let transform = Transform {
+                rotation,
+                flip_horizontal,
+                flip_vertical,
+            };
+            let frame = Frame {
+                data: &data,
+                width: 3,
+                height: 2,
+                pitch: 16,
+                format: PixelFormat::Rgba,
+            };
+            let mut transformer = Transformer::new(transform);
+            let output = transformer.apply(frame);
+            let size = match rotation {
+                Rotation::R0 | Rotation::R180 => (3, 2),
+                Rotation::R90 | Rotation::R270 => (2, 3),
+            };
+            assert_eq!((output.width, output.height), size, "{transform:?}");
+            let mut pixels = Vec::new();
+            for row in output.data.chunks(output.pitch) {
+                for pixel in row[..output.width as usize * 4].chunks_exact(4) {
+                    assert_eq!(pixel[1..], [0, 0, 0xFF], "{transform:?}");
+                    pixels.push(pixel[0]);
+                }
+            }
+            assert_eq!(pixels, expected, "{transform:?}");
+        }
+    }
+
+    #[test]
+    fn source_vector_follows_the_rotation() {
+        let transform = Transform {
+            rotation: Rotation::R90,
+            ..Default::default()
+        };
+        //Right on the rotated frame is up on the frame
+        assert_eq!(transform.source_vector(1, 0), (0, -1));
+        let transform = Transform {
+            flip_horizontal: true,
+            ..Default::default()
+        };
+        assert_eq!(transform.source_vector(3, 2), (-3, 2));
+    }
+}
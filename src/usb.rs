@@ -5,11 +5,14 @@ use std::{
         Arc, Mutex,
     },
     thread::sleep,
-    time::{Duration},
+    time::{Duration, Instant},
 };
 
 use crate::{
-    capture::capture_screen,
+    capture::{capture_screen, CaptureOptions},
+    encoder::EncoderOptions,
+    message::{codec_message, MessageReader},
+    pipeline::Pipeline,
     error::{self, GabinatorError, GabinatorResult, Logger, LoggerLevel},
 };
 
@@ -91,7 +94,7 @@ pub fn find_compatible_usb<'a>(
     return Ok(compatible_devices);
 }
 
-pub fn connect_to_device(pid: u16, vid: u16,quality: u8) -> Result<GabinatorResult, GabinatorError> {
+pub fn connect_to_device(pid: u16, vid: u16, encoder_options: EncoderOptions, options: CaptureOptions) -> Result<GabinatorResult, GabinatorError> {
     let config = Logger::get_config_content();
     let device = match open_device_with_vid_pid(vid, pid) {
         Some(a) => a,
@@ -175,23 +178,83 @@ pub fn connect_to_device(pid: u16, vid: u16,quality: u8) -> Result<GabinatorResu
         }
     };
 
+    let mut pipeline = match Pipeline::new(&encoder_options, options) {
+        Ok(a) => a,
+        Err(a) => {
+            return Err(GabinatorError::newUSB(
+                format!("Not able to start the pipeline: {a:?}"),
+                LoggerLevel::Critical,
+                Some(config.clone()),
+            ))
+        }
+    };
+
+    //Acks and the rest of the receiver messages come through the IN endpoint, if there is one
+    let endpoint_in = find_endpoint(&clone_device.lock().unwrap().device(), Direction::In);
+    if endpoint_in.is_none() {
+        Logger::log(
+            "There is no bulk IN endpoint, the messages of the receiver are ignored".to_string(),
+            LoggerLevel::Warning,
+            Some(config.clone()),
+        );
+    }
+    let mut reader = MessageReader::default();
+
+    //The receiver needs to know the codec before the first frame
+    let codec = codec_message(pipeline.codec());
+    if let Some(a) = send_USB_data(&codec, &clone_device.lock().unwrap(), endpoint_data.address) {
+        return Err(GabinatorError::newUSB(
+            format!("Not able to send the codec: {a}"),
+            LoggerLevel::Critical,
+            Some(config.clone()),
+        ));
+    }
+
     while running.load(Ordering::SeqCst) {
         let device_new = clone_device.lock().unwrap();
 
+        if let Some(endpoint_in) = &endpoint_in {
+            read_messages(&device_new, endpoint_in.address, &mut reader);
+            while let Some(message) = reader.next_message() {
+                match message {
+                    Ok(a) => pipeline.handle_message(a),
+                    Err(a) => Logger::log(a, LoggerLevel::Warning, Some(config.clone())),
+                }
+            }
+        }
+
         //TODO: Separar en funcion
-        let data = match capture_screen(quality) {
-            Ok(a) => a,
+        let packet = match pipeline.next_frame() {
+            Ok(Some(a)) => a,
+            //Nothing changed on the screen
+            Ok(None) => continue,
             Err(a) => {
                 GabinatorError::newUSB(
-                    format!("Error capturing image: {a}"),
+                    format!("Error capturing image: {a:?}"),
                     LoggerLevel::Debug,
                     Some(config.clone()),
                 );
                 continue;
             }
         };
+        Logger::log(
+            format!("Sending frame, {} areas changed", pipeline.dirty_rects().len()),
+            LoggerLevel::Debug,
+            Some(config.clone()),
+        );
 
-        match send_USB_data(&data, &device_new, endpoint_data.address) {
+        let start = Instant::now();
+        //The header goes in its own transfer, so the frame buffer is not copied
+        let result = match packet.header() {
+            Some(header) => send_USB_data(&header, &device_new, endpoint_data.address)
+                .or_else(|| send_USB_data(packet.data(), &device_new, endpoint_data.address)),
+            None => send_USB_data(packet.data(), &device_new, endpoint_data.address),
+        };
+        if result.is_none() {
+            pipeline.report_sent(packet.data().len(), start.elapsed());
+        }
+        pipeline.recycle(packet);
+        match result {
             None => continue,
             Some(a) => GabinatorError::newUSB(
                 format!("Not able to write bulk: {a}"),
@@ -201,6 +264,8 @@ pub fn connect_to_device(pid: u16, vid: u16,quality: u8) -> Result<GabinatorResu
         };
     }
 
+    //The receiver can not release what it held down anymore
+    pipeline.client_disconnected();
     while stop_signal.load(Ordering::SeqCst) {}
     return Ok(GabinatorResult::newUSB(
         "Session succes",
@@ -454,6 +519,11 @@ impl fmt::Display for endpoint {
 }
 
 pub fn find_bulk_endpoint(device: &Device<GlobalContext>) -> Option<endpoint> {
+    find_endpoint(device, Direction::Out)
+}
+
+//First bulk endpoint going in that direction
+pub fn find_endpoint(device: &Device<GlobalContext>, direction: Direction) -> Option<endpoint> {
     let descriptor = device.device_descriptor().unwrap();
     for e in 0..descriptor.num_configurations() {
         let config = match device.config_descriptor(e) {
@@ -466,7 +536,7 @@ pub fn find_bulk_endpoint(device: &Device<GlobalContext>) -> Option<endpoint> {
         for interface in config.interfaces() {
             for int_descriptors in interface.descriptors() {
                 for endpoint in int_descriptors.endpoint_descriptors() {
-                    if endpoint.direction() == Direction::Out
+                    if endpoint.direction() == direction
                         && endpoint.transfer_type() == TransferType::Bulk
                     {
                         return Some(endpoint {
@@ -486,17 +556,29 @@ pub fn find_bulk_endpoint(device: &Device<GlobalContext>) -> Option<endpoint> {
 pub fn capture_and_send(
     handler: &DeviceHandle<GlobalContext>,
     endpoint_data: u8,
-    quality: u8
+    encoder_options: &EncoderOptions,
+    options: CaptureOptions,
 ) -> Option<rusb::Error> {
-    let data = match capture_screen(quality) {
+    let data = match capture_screen(encoder_options, options) {
         Ok(a) => a,
         Err(a) => return Some(rusb::Error::Other),
     };
     send_USB_data(&data, handler, endpoint_data)
 }
 
+//Reads whatever the receiver sent without waiting for it
+pub fn read_messages(handler: &DeviceHandle<GlobalContext>, endpoint_in: u8, reader: &mut MessageReader) {
+    let mut buffer = [0u8; 512];
+    while let Ok(a) = handler.read_bulk(endpoint_in, &mut buffer, Duration::from_millis(1)) {
+        if a == 0 {
+            break;
+        }
+        reader.push(&buffer[..a]);
+    }
+}
+
 pub fn send_USB_data(
-    data: &Vec<u8>,
+    data: &[u8],
     handler: &DeviceHandle<GlobalContext>,
     endpoint_data: u8,
 ) -> Option<rusb::Error> {
@@ -0,0 +1,203 @@
+use std::str::FromStr;
+
+#[cfg(target_os = "linux")]
+use crate::capture::Rect;
+#[cfg(target_os = "linux")]
+use crate::error::{Logger, LoggerLevel};
+
+//How the window to capture is chosen
+#[derive(Clone, Debug)]
+pub enum WindowSelector {
+    //X window ID, decimal or 0x prefixed hex
+    Id(u32),
+    //First top level window whose title contains this text
+    Title(String),
+    //First top level window owned by this PID or by a process with this name
+    Process(String),
+}
+
+//Parses a window ID like the ones printed by xwininfo or xdotool
+pub fn parse_window_id(value: &str) -> Result<u32, String> {
+    let result = match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => u32::from_str(value),
+    };
+    result.map_err(|a| format!("Not a valid window ID {value}: {a}"))
+}
+
+#[cfg(target_os = "linux")]
+fn intern_atom(conn: &xcb::Connection, name: &str) -> xcb::Result<xcb::x::Atom> {
+    let reply = conn.wait_for_reply(conn.send_request(&xcb::x::InternAtom {
+        only_if_exists: true,
+        name: name.as_bytes(),
+    }))?;
+    Ok(reply.atom())
+}
+
+//Top level windows, from the window manager list or from the window tree if there is no EWMH WM
+#[cfg(target_os = "linux")]
+fn top_level_windows(conn: &xcb::Connection, root: xcb::x::Window) -> xcb::Result<Vec<xcb::x::Window>> {
+    use xcb::x;
+
+    let client_list = intern_atom(conn, "_NET_CLIENT_LIST")?;
+    if client_list != x::ATOM_NONE {
+        let reply = conn.wait_for_reply(conn.send_request(&x::GetProperty {
+            delete: false,
+            window: root,
+            property: client_list,
+            r#type: x::ATOM_WINDOW,
+            long_offset: 0,
+            long_length: u32::MAX,
+        }))?;
+        if reply.format() == 32 && !reply.value::<x::Window>().is_empty() {
+            return Ok(reply.value::<x::Window>().to_vec());
+        }
+    }
+
+    let tree = conn.wait_for_reply(conn.send_request(&x::QueryTree { window: root }))?;
+    Ok(tree.children().to_vec())
+}
+
+#[cfg(target_os = "linux")]
+fn window_title(conn: &xcb::Connection, window: xcb::x::Window) -> xcb::Result<String> {
+    use xcb::x;
+
+    let net_wm_name = intern_atom(conn, "_NET_WM_NAME")?;
+    for property in [net_wm_name, x::ATOM_WM_NAME] {
+        if property == x::ATOM_NONE {
+            continue;
+        }
+        let reply = conn.wait_for_reply(conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property,
+            r#type: x::ATOM_ANY,
+            long_offset: 0,
+            long_length: 1024,
+        }))?;
+        if reply.format() == 8 && !reply.value::<u8>().is_empty() {
+            return Ok(String::from_utf8_lossy(reply.value::<u8>()).to_string());
+        }
+    }
+    Ok(String::new())
+}
+
+#[cfg(target_os = "linux")]
+fn window_pid(conn: &xcb::Connection, window: xcb::x::Window) -> xcb::Result<Option<u32>> {
+    use xcb::x;
+
+    let net_wm_pid = intern_atom(conn, "_NET_WM_PID")?;
+    if net_wm_pid == x::ATOM_NONE {
+        return Ok(None);
+    }
+    let reply = conn.wait_for_reply(conn.send_request(&x::GetProperty {
+        delete: false,
+        window,
+        property: net_wm_pid,
+        r#type: x::ATOM_CARDINAL,
+        long_offset: 0,
+        long_length: 1,
+    }))?;
+    if reply.format() != 32 {
+        return Ok(None);
+    }
+    Ok(reply.value::<u32>().first().copied())
+}
+
+//PIDs that match a PID number or a process name
+#[cfg(target_os = "linux")]
+fn matching_pids(process: &str) -> Vec<u32> {
+    let system = sysinfo::System::new_all();
+    if let Ok(pid) = process.parse::<u32>() {
+        return match system.process(sysinfo::Pid::from_u32(pid)) {
+            Some(_) => vec![pid],
+            None => Vec::new(),
+        };
+    }
+    system
+        .processes_by_name(process)
+        .map(|a| a.pid().as_u32())
+        .collect()
+}
+
+//Finds the window that matches the selector, None if there is no such window
+#[cfg(target_os = "linux")]
+pub fn find_window(
+    conn: &xcb::Connection,
+    root: xcb::x::Window,
+    selector: &WindowSelector,
+) -> xcb::Result<Option<xcb::x::Window>> {
+    use xcb::XidNew;
+
+    match selector {
+        WindowSelector::Id(id) => {
+            //SAFETY: the ID is checked by asking the server for its geometry
+            let window = unsafe { xcb::x::Window::new(*id) };
+            match conn.wait_for_reply(conn.send_request(&xcb::x::GetGeometry {
+                drawable: xcb::x::Drawable::Window(window),
+            })) {
+                Ok(_) => Ok(Some(window)),
+                Err(xcb::Error::Protocol(_)) => Ok(None),
+                Err(a) => Err(a),
+            }
+        }
+        WindowSelector::Title(title) => {
+            for window in top_level_windows(conn, root)? {
+                //The window can be destroyed while the list is walked, it is skipped then
+                match window_title(conn, window) {
+                    Ok(a) if a.contains(title.as_str()) => return Ok(Some(window)),
+                    Ok(_) | Err(xcb::Error::Protocol(_)) => {}
+                    Err(a) => return Err(a),
+                }
+            }
+            Ok(None)
+        }
+        WindowSelector::Process(process) => {
+            let pids = matching_pids(process);
+            if pids.is_empty() {
+                Logger::log(
+                    format!("There is no process matching {process}"),
+                    LoggerLevel::Warning,
+                    None,
+                );
+                return Ok(None);
+            }
+            for window in top_level_windows(conn, root)? {
+                match window_pid(conn, window) {
+                    Ok(Some(pid)) if pids.contains(&pid) => return Ok(Some(window)),
+                    Ok(_) | Err(xcb::Error::Protocol(_)) => {}
+                    Err(a) => return Err(a),
+                }
+            }
+            Ok(None)
+        }
+    }
+}
+
+//Current position and size of the window in root coordinates
+#[cfg(target_os = "linux")]
+pub fn window_area(
+    conn: &xcb::Connection,
+    root: xcb::x::Window,
+    window: xcb::x::Window,
+) -> xcb::Result<Rect> {
+    use xcb::x;
+
+    let geometry_cookie = conn.send_request(&x::GetGeometry {
+        drawable: x::Drawable::Window(window),
+    });
+    let position_cookie = conn.send_request(&x::TranslateCoordinates {
+        src_window: window,
+        dst_window: root,
+        src_x: 0,
+        src_y: 0,
+    });
+    let geometry = conn.wait_for_reply(geometry_cookie)?;
+    let position = conn.wait_for_reply(position_cookie)?;
+    Ok(Rect {
+        x: position.dst_x(),
+        y: position.dst_y(),
+        width: geometry.width(),
+        height: geometry.height(),
+    })
+}